@@ -467,6 +467,8 @@ fn take_rusage() -> Option<RusageSnapshot> {
 }
 
 #[cfg(unix)]
+// rusage fields are `c_long`, which is not `i64` on every target.
+#[allow(clippy::unnecessary_cast)]
 fn resource_usage_delta(
     start: Option<RusageSnapshot>,
     end: Option<RusageSnapshot>,
@@ -18,6 +18,34 @@ pub struct Cli {
     #[arg(long)]
     pub max_depth: Option<usize>,
 
+    /// Walk the whole tree but print only down to depth N, with totals of everything below
+    #[arg(long, value_name = "N")]
+    pub summary_depth: Option<usize>,
+
+    /// Emit entries breadth-first (level by level); ndjson/csv only
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub bfs: bool,
+
+    /// Show at most N children per directory, then a "… N more entries" summary
+    #[arg(long, value_name = "N")]
+    pub max_entries_per_dir: Option<usize>,
+
+    /// Stop after N entries (excluding the root) and end the output with a truncation trailer
+    #[arg(long, value_name = "N")]
+    pub limit_entries: Option<usize>,
+
+    /// Keep the whole output within N bytes (UTF-8, without color codes), trailer included
+    #[arg(long, value_name = "BYTES")]
+    pub limit_output_bytes: Option<usize>,
+
+    /// Omit the "N directories, M files" footer (plain)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub no_report: bool,
+
+    /// Report totals, largest files, extensions, deepest path and counts of symlinks/loops/errors
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub stats: bool,
+
     /// Show dotfiles
     #[arg(long, action = ArgAction::SetTrue)]
     pub hidden: bool,
@@ -26,26 +54,63 @@ pub struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     pub follow_symlinks: bool,
 
-    /// Sort mode
+    /// Sort mode (size and mtime list largest/newest first; directories use recursive sizes)
     #[arg(long, value_enum, default_value_t = SortMode::None)]
     pub sort: SortMode,
 
+    /// Reverse the sort order
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub reverse: bool,
+
+    /// Compare names by Unicode-normalized (NFC) text instead of raw bytes
+    #[arg(long, value_enum)]
+    pub collation: Option<Collation>,
+
+    /// Warn about sibling names that differ only in Unicode normalization (NFC/NFD)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub check_normalization: bool,
+
     /// Directories first when sorting
     #[arg(long, action = ArgAction::SetTrue)]
     pub dirs_first: bool,
 
-    /// Include glob(s). Multiple allowed.
+    /// Include glob(s). Multiple allowed; "!pat" negates, "/"-containing globs anchor to the root.
     #[arg(long = "include")]
     pub includes: Vec<String>,
 
-    /// Exclude glob(s). Multiple allowed.
+    /// Exclude glob(s). Multiple allowed; "!pat" re-includes, a trailing "/" matches directories only.
     #[arg(long = "exclude")]
     pub excludes: Vec<String>,
 
+    /// Include files by extension, e.g. "rs,toml" (repeatable)
+    #[arg(long = "ext", value_delimiter = ',')]
+    pub exts: Vec<String>,
+
+    /// Include files of a built-in language type, e.g. "rust,python" (see --list-langs)
+    #[arg(long = "lang", value_delimiter = ',')]
+    pub langs: Vec<String>,
+
+    /// List built-in language types for --lang and exit
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub list_langs: bool,
+
+    /// Hide directories that have no visible entries after filtering
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub prune: bool,
+
     /// Pattern syntax for include/exclude filters
     #[arg(long = "pattern-syntax", value_enum, default_value_t = PatternSyntax::Glob)]
     pub pattern_syntax: PatternSyntax,
 
+    /// Match include/exclude/--filter-regex patterns case-insensitively
+    /// (a single pattern can opt in with an "i:" prefix, e.g. "i:*.jpg")
+    #[arg(long, action = ArgAction::SetTrue, conflicts_with = "smart_case")]
+    pub ignore_case: bool,
+
+    /// Ignore case only for patterns that contain no uppercase letters
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub smart_case: bool,
+
     /// name/path match basis for globs
     #[arg(long, value_enum, default_value_t = MatchMode::Name)]
     pub match_mode: MatchMode,
@@ -54,19 +119,46 @@ pub struct Cli {
     #[arg(long = "filter-regex")]
     pub filter_regex: Option<String>,
 
-    /// File size filter, e.g. ">1MB" or "<=10k"
+    /// File size filter, e.g. ">1MB", "10k..1M" or ">=10k,<1M" (clauses are ANDed)
     #[arg(long = "filter-size")]
     pub filter_size: Option<String>,
 
-    /// Modified-time filter window like "3d", "10m", "2h"
+    /// Directory filter on the recursive total size of contents (same syntax as --filter-size).
+    /// Totals count every file below, ignoring --include/--exclude; symlinked directories pass
+    #[arg(long = "filter-dir-size")]
+    pub filter_dir_size: Option<String>,
+
+    /// Modified-time filter: "3d" (within), ">30d" (older than),
+    /// ">=2025-01-01", or a range "2025-01-01..2025-03-31" / "30d..7d" (both ends inclusive)
     #[arg(long = "filter-mtime")]
     pub filter_mtime: Option<String>,
 
-    /// Permission filter (octal, e.g. 755)
+    /// Status-change time filter (creation time on Windows); same syntax as --filter-mtime
+    #[arg(long = "filter-ctime")]
+    pub filter_ctime: Option<String>,
+
+    /// Access-time filter; same syntax as --filter-mtime
+    #[arg(long = "filter-atime")]
+    pub filter_atime: Option<String>,
+
+    /// Permission filter like find -perm: "755" (exact), "-4000" (all bits),
+    /// "/022" (any bit) or symbolic "u+x", "g+s,+t", "u=rwx,go=rx" (exact)
     #[arg(long = "filter-perm")]
     pub filter_perm: Option<String>,
 
-    /// Type filter: file|dir|symlink (repeatable)
+    /// Only files writable by others (o+w)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub world_writable: bool,
+
+    /// Only files with any execute bit set
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub executable: bool,
+
+    /// Only files with the setuid bit set
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub setuid: bool,
+
+    /// Type filter: file|dir|symlink|lfs (repeatable; lfs implies --lfs)
     #[arg(long = "type", value_enum)]
     pub types: Vec<TypeFilter>,
 
@@ -78,10 +170,49 @@ pub struct Cli {
     #[arg(long, action = ArgAction::SetTrue)]
     pub git_status: bool,
 
+    /// Show only changed entries and directories that contain changes (implies git status)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub git_changed_only: bool,
+
+    /// Also mark ignored entries as `!!`, like `git status --ignored` (implies git status)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub git_ignored: bool,
+
+    /// Show tracked files deleted from the working tree as dimmed entries (implies git status)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub git_ghosts: bool,
+
+    /// Also load git status inside submodules and nested repositories, so their changes show up
+    /// on the files within them (implies git status)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub git_submodules: bool,
+
+    /// Detect Git LFS pointer files and report the real object size, its oid and whether
+    /// the object is downloaded to .git/lfs/objects
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub lfs: bool,
+
+    /// Render the tree of a git revision (commit, tag or branch) instead of the working tree
+    #[arg(long, value_name = "REF")]
+    pub rev: Option<String>,
+
+    /// Where to read the tree from: the working tree, or exactly what is staged in the index
+    /// (conflicted paths list all their stages)
+    #[arg(long, value_enum, default_value_t = Source::Worktree)]
+    pub source: Source,
+
+    /// Annotate entries with the last commit that touched them (hash, author, date, subject)
+    #[arg(long, action = ArgAction::SetTrue)]
+    pub git_log: bool,
+
     /// Enable git rename detection (implies git status)
     #[arg(long, action = ArgAction::SetTrue)]
     pub git_rename: bool,
 
+    /// Similarity (0-100) at which a delete/add pair counts as a rename; implies --git-rename
+    #[arg(long, value_name = "PERCENT")]
+    pub rename_threshold: Option<u16>,
+
     /// Color output
     #[arg(long, value_enum, default_value_t = ColorMode::Never)]
     pub color: ColorMode,
@@ -124,6 +255,73 @@ pub enum Cmd {
         #[arg(long, value_enum, default_value_t = Format::Plain)]
         format: Format,
     },
+    /// Annotate the tree with per-file and per-directory commit counts, line changes and authors
+    /// from git history (tree filters and formats apply; pass them before `churn`)
+    Churn {
+        /// Root path (defaults to the tree path or ".")
+        path: Option<PathBuf>,
+
+        /// Only count commits since this point (e.g. 90d, 2w, 2025-01-01); defaults to all history
+        #[arg(long)]
+        since: Option<String>,
+    },
+    /// Count files and bytes per extension and language (tree filters apply; pass them before `stats`)
+    Stats {
+        /// Root path (defaults to the tree path or ".")
+        path: Option<PathBuf>,
+
+        /// Also break down each directory down to this depth (0 = totals only)
+        #[arg(long, default_value_t = 1)]
+        depth: usize,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = StatsFormat::Table)]
+        format: StatsFormat,
+    },
+    /// List the N largest, newest or oldest files (tree filters apply; pass them before `top`)
+    Top {
+        /// Root path (defaults to the tree path or ".")
+        path: Option<PathBuf>,
+
+        /// Ranking key
+        #[arg(long, value_enum, default_value_t = TopBy::Size)]
+        by: TopBy,
+
+        /// Number of files to list
+        #[arg(long, default_value_t = 20)]
+        n: usize,
+
+        /// Also draw a mini-tree of where the listed files live (plain)
+        #[arg(long, action = ArgAction::SetTrue)]
+        tree: bool,
+
+        /// Output format
+        #[arg(long, value_enum, default_value_t = TopFormat::Plain)]
+        format: TopFormat,
+    },
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TopBy {
+    /// Largest first
+    Size,
+    /// Most recently modified first
+    Mtime,
+    /// Least recently modified first
+    Age,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum TopFormat {
+    Plain,
+    Json,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum StatsFormat {
+    Table,
+    Json,
+    Csv,
 }
 
 #[derive(ValueEnum, Clone, Debug)]
@@ -139,6 +337,24 @@ pub enum EncodingMode {
 pub enum SortMode {
     None,
     Name,
+    /// Name with digit runs compared numerically (file2 < file10)
+    Natural,
+    Size,
+    Mtime,
+    Ext,
+    /// Directories, files, symlinks, then others
+    Kind,
+    GitStatus,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Collation {
+    /// NFKC with full case folding (full-width letters sort with ASCII)
+    Unicode,
+    /// NFC with ASCII-only case folding
+    AsciiCi,
+    /// Like unicode, and katakana sorts together with hiragana
+    Ja,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
@@ -158,6 +374,15 @@ pub enum TypeFilter {
     File,
     Dir,
     Symlink,
+    /// Git LFS pointer files
+    Lfs,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Source {
+    Worktree,
+    /// Entries of the git index with their modes, blob sizes and stage numbers
+    Index,
 }
 
 #[derive(Copy, Clone, Debug, ValueEnum)]
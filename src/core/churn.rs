@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Context, Result};
+use git2::{DiffOptions, Patch, Sort};
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::{Cli, GitignoreMode};
+use crate::core::tree::{
+    discover_repository, parse_time_start, run_tree_at, workdir_relative, Completion,
+};
+
+/// パス（ワークツリー相対）ごとの変更頻度。ディレクトリは配下の合計で、ルートは空パス
+pub(crate) struct ChurnIndex {
+    workdir: PathBuf,
+    cwd: PathBuf,
+    stats: HashMap<PathBuf, ChurnStats>,
+}
+
+/// エントリに添える集計
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+pub(crate) struct ChurnStats {
+    pub commits: u32,
+    pub added: u64,
+    pub removed: u64,
+    pub authors: u32,
+    /// 同じ種類（ファイル／ディレクトリ）の最大コミット数に対する割合（%）。色分け用
+    #[serde(skip)]
+    pub heat: u8,
+}
+
+#[derive(Default)]
+struct ChurnAcc {
+    commits: u32,
+    added: u64,
+    removed: u64,
+    authors: HashSet<String>,
+    /// 同じコミットを二重に数えないための最後に数えたコミットの番号
+    last_commit: Option<usize>,
+    is_dir: bool,
+}
+
+pub fn run_churn(cli: &Cli, path: Option<&Path>, since: Option<&str>) -> Result<Completion> {
+    let root = path
+        .map(Path::to_path_buf)
+        .or_else(|| cli.path.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    if matches!(cli.gitignore, GitignoreMode::On) {
+        eprintln!("[warn] --gitignore on ignored by churn");
+    }
+    let since = since
+        .map(|value| parse_time_start(value, "--since"))
+        .transpose()?;
+    let churn = ChurnIndex::build(&root, since, cli.rev.as_deref())?;
+    run_tree_at(&root, cli, Some(churn))
+}
+
+impl ChurnIndex {
+    /// HEAD（`--rev` があればそのコミット）から新しい順に辿り、`since` より古いコミットに達したら
+    /// 打ち切る。`git log --numstat` と同じくマージコミットは数えない
+    pub(crate) fn build(root: &Path, since: Option<SystemTime>, rev: Option<&str>) -> Result<Self> {
+        let cwd = env::current_dir()?;
+        let repo = discover_repository(root).context("not a git repository")?;
+        let workdir = repo
+            .workdir()
+            .map(Path::to_path_buf)
+            .context("repository has no workdir")?;
+        let since_secs = since
+            .and_then(|at| at.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs() as i64);
+
+        let mut accs: HashMap<PathBuf, ChurnAcc> = HashMap::new();
+        let mut walk = repo.revwalk()?;
+        walk.set_sorting(Sort::TIME)?;
+        match rev {
+            Some(rev) => {
+                let commit = repo
+                    .revparse_single(rev)
+                    .and_then(|obj| obj.peel_to_commit())
+                    .map_err(|err| anyhow!("invalid --rev value: {rev} ({})", err.message()))?;
+                walk.push(commit.id())?;
+            }
+            None => walk.push_head()?,
+        }
+        let mut opts = DiffOptions::new();
+        opts.context_lines(0);
+        for (ordinal, oid) in walk.enumerate() {
+            let commit = repo.find_commit(oid?)?;
+            if since_secs.is_some_and(|since| commit.time().seconds() < since) {
+                break;
+            }
+            if commit.parent_count() > 1 {
+                continue;
+            }
+            let author = commit.author();
+            let author = author
+                .email()
+                .or_else(|| author.name())
+                .unwrap_or_default()
+                .to_ascii_lowercase();
+            let parent_tree = match commit.parents().next() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+            let diff = repo.diff_tree_to_tree(
+                parent_tree.as_ref(),
+                Some(&commit.tree()?),
+                Some(&mut opts),
+            )?;
+            for idx in 0..diff.deltas().len() {
+                let Some(delta) = diff.get_delta(idx) else {
+                    continue;
+                };
+                let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+                    continue;
+                };
+                let (added, removed) = match Patch::from_diff(&diff, idx)? {
+                    Some(patch) => {
+                        let (_, added, removed) = patch.line_stats()?;
+                        (added as u64, removed as u64)
+                    }
+                    None => (0, 0),
+                };
+                for (level, ancestor) in path.ancestors().enumerate() {
+                    let acc = accs.entry(ancestor.to_path_buf()).or_default();
+                    acc.is_dir = level > 0;
+                    acc.added += added;
+                    acc.removed += removed;
+                    acc.authors.insert(author.clone());
+                    if acc.last_commit != Some(ordinal) {
+                        acc.last_commit = Some(ordinal);
+                        acc.commits += 1;
+                    }
+                }
+            }
+        }
+
+        let max_of = |dirs: bool| {
+            accs.iter()
+                .filter(|(path, acc)| acc.is_dir == dirs && !path.as_os_str().is_empty())
+                .map(|(_, acc)| acc.commits)
+                .max()
+                .unwrap_or(0)
+        };
+        let (max_files, max_dirs) = (max_of(false), max_of(true));
+        let stats = accs
+            .into_iter()
+            .map(|(path, acc)| {
+                let max = if acc.is_dir { max_dirs } else { max_files };
+                let heat = if path.as_os_str().is_empty() || max == 0 {
+                    0
+                } else {
+                    (acc.commits * 100 / max).min(100) as u8
+                };
+                let stats = ChurnStats {
+                    commits: acc.commits,
+                    added: acc.added,
+                    removed: acc.removed,
+                    authors: acc.authors.len() as u32,
+                    heat,
+                };
+                (path, stats)
+            })
+            .collect();
+
+        Ok(Self {
+            workdir,
+            cwd,
+            stats,
+        })
+    }
+
+    pub(crate) fn stats_for(&self, path: &Path) -> Option<ChurnStats> {
+        let rel = workdir_relative(&self.workdir, &self.cwd, path)?;
+        self.stats.get(&rel).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::Repository;
+    use std::fs;
+
+    #[test]
+    fn churn_counts_commits_lines_and_authors_up_the_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        let commit = |author: &str, files: &[(&str, &str)]| {
+            for (name, body) in files {
+                let path = root.join(name);
+                fs::create_dir_all(path.parent().unwrap()).unwrap();
+                fs::write(path, body).unwrap();
+            }
+            let mut index = repo.index().unwrap();
+            index
+                .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+                .unwrap();
+            index.write().unwrap();
+            let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+            let sig = git2::Signature::now(author, &format!("{author}@example.com")).unwrap();
+            let parents: Vec<_> = repo
+                .head()
+                .ok()
+                .and_then(|head| head.peel_to_commit().ok())
+                .into_iter()
+                .collect();
+            let parents: Vec<_> = parents.iter().collect();
+            repo.commit(Some("HEAD"), &sig, &sig, "change", &tree, &parents)
+                .unwrap();
+        };
+        commit("alice", &[("src/hot.rs", "a\nb\n"), ("src/cold.rs", "x\n")]);
+        commit("bob", &[("src/hot.rs", "a\nB\nc\n")]);
+        commit(
+            "alice",
+            &[("src/hot.rs", "a\nB\nc\nd\n"), ("README", "r\n")],
+        );
+
+        let index = ChurnIndex::build(root, None, None).unwrap();
+        let hot = index.stats_for(&root.join("src/hot.rs")).unwrap();
+        assert_eq!(
+            (hot.commits, hot.added, hot.removed, hot.authors, hot.heat),
+            (3, 5, 1, 2, 100)
+        );
+        let cold = index.stats_for(&root.join("src/cold.rs")).unwrap();
+        assert_eq!((cold.commits, cold.authors, cold.heat), (1, 1, 33));
+        let src = index.stats_for(&root.join("src")).unwrap();
+        assert_eq!((src.commits, src.added, src.removed), (3, 6, 1));
+        let all = index.stats_for(root).unwrap();
+        assert_eq!((all.commits, all.authors), (3, 2));
+
+        // 未来の時点からは何も数えない
+        let later = SystemTime::now() + std::time::Duration::from_secs(3600);
+        let index = ChurnIndex::build(root, Some(later), None).unwrap();
+        assert_eq!(index.stats_for(&root.join("src/hot.rs")), None);
+    }
+}
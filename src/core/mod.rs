@@ -1,3 +1,6 @@
+pub mod churn;
 pub mod diff;
+pub mod stats;
+pub mod top;
 pub mod tree;
 pub mod tree_gitignore;
@@ -0,0 +1,297 @@
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use crate::cli::{Cli, GitignoreMode, StatsFormat};
+use crate::core::tree::{format_size_human, visit_tree, TreeItem};
+use crate::utils::lang_of;
+
+/// `printree stats` の集計結果。ルート全体と、指定した深さまでの各ディレクトリ（配下すべて）を持つ
+#[derive(Debug, Serialize)]
+struct StatsReport {
+    root: String,
+    depth: usize,
+    scopes: Vec<ScopeReport>,
+}
+
+#[derive(Debug, Serialize)]
+struct ScopeReport {
+    /// ルートからの相対パス（ルート自身は "."）
+    path: String,
+    depth: usize,
+    files: u64,
+    bytes: u64,
+    extensions: Vec<GroupCount>,
+    languages: Vec<GroupCount>,
+}
+
+#[derive(Debug, Serialize)]
+struct GroupCount {
+    /// 拡張子なし・該当言語なしは null
+    name: Option<String>,
+    files: u64,
+    bytes: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Bucket {
+    files: u64,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct Scope {
+    total: Bucket,
+    exts: HashMap<String, Bucket>,
+    langs: HashMap<String, Bucket>,
+}
+
+pub fn run_stats(cli: &Cli, path: Option<&Path>, depth: usize, format: StatsFormat) -> Result<()> {
+    let root = path
+        .map(Path::to_path_buf)
+        .or_else(|| cli.path.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    if matches!(cli.gitignore, GitignoreMode::On) {
+        eprintln!("[warn] --gitignore on ignored by stats");
+    }
+    let report = collect_stats(cli, &root, depth)?;
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    match format {
+        StatsFormat::Table => write_table(&mut stdout, &report)?,
+        StatsFormat::Json => {
+            serde_json::to_writer_pretty(&mut stdout, &report)?;
+            writeln!(&mut stdout)?;
+        }
+        StatsFormat::Csv => write_csv(&mut stdout, &report)?,
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn collect_stats(cli: &Cli, root: &Path, depth: usize) -> Result<StatsReport> {
+    let mut scopes: BTreeMap<PathBuf, Scope> = BTreeMap::new();
+    scopes.insert(PathBuf::new(), Scope::default());
+
+    visit_tree(cli, root, |item: &TreeItem<'_>| {
+        let rel = item.path.strip_prefix(root).unwrap_or(item.path);
+        if item.is_dir && item.depth <= depth {
+            scopes.entry(rel.to_path_buf()).or_default();
+        }
+        if !item.is_file {
+            return;
+        }
+
+        let size = item.size.unwrap_or(0);
+        let ext = Path::new(item.name)
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let lang = lang_of(item.name)
+            .map(|lang| lang.name.to_string())
+            .unwrap_or_default();
+
+        // ルートと、深さ `depth` までの祖先ディレクトリすべてに加算する
+        let mut ancestors = vec![PathBuf::new()];
+        let mut prefix = PathBuf::new();
+        let parents = rel.parent().map(|p| p.components().count()).unwrap_or(0);
+        for component in rel.components().take(parents.min(depth)) {
+            prefix.push(component);
+            ancestors.push(prefix.clone());
+        }
+        for key in ancestors {
+            let scope = scopes.entry(key).or_default();
+            scope.total.add(size);
+            scope.exts.entry(ext.clone()).or_default().add(size);
+            scope.langs.entry(lang.clone()).or_default().add(size);
+        }
+    })?;
+
+    let scopes = scopes
+        .into_iter()
+        .map(|(rel, scope)| {
+            let depth = rel.components().count();
+            let path = if depth == 0 {
+                String::from(".")
+            } else {
+                rel.display().to_string()
+            };
+            ScopeReport {
+                path,
+                depth,
+                files: scope.total.files,
+                bytes: scope.total.bytes,
+                extensions: group_counts(scope.exts),
+                languages: group_counts(scope.langs),
+            }
+        })
+        .collect();
+
+    Ok(StatsReport {
+        root: root.display().to_string(),
+        depth,
+        scopes,
+    })
+}
+
+impl Bucket {
+    fn add(&mut self, size: u64) {
+        self.files += 1;
+        self.bytes = self.bytes.saturating_add(size);
+    }
+}
+
+/// ファイル数の多い順（同数なら名前順、名前なしは最後）
+fn group_counts(groups: HashMap<String, Bucket>) -> Vec<GroupCount> {
+    let mut counts: Vec<GroupCount> = groups
+        .into_iter()
+        .map(|(name, bucket)| GroupCount {
+            name: (!name.is_empty()).then_some(name),
+            files: bucket.files,
+            bytes: bucket.bytes,
+        })
+        .collect();
+    counts.sort_by(|a, b| {
+        b.files
+            .cmp(&a.files)
+            .then_with(|| a.name.is_none().cmp(&b.name.is_none()))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    counts
+}
+
+/// 表・CSV の 1 行（path, group, key, files, bytes）
+fn report_rows(report: &StatsReport) -> Vec<(&str, &'static str, &str, u64, u64)> {
+    let mut rows = Vec::new();
+    for scope in &report.scopes {
+        rows.push((scope.path.as_str(), "total", "", scope.files, scope.bytes));
+        for (group, counts) in [("ext", &scope.extensions), ("lang", &scope.languages)] {
+            for count in counts {
+                let key = count.name.as_deref().unwrap_or("");
+                rows.push((scope.path.as_str(), group, key, count.files, count.bytes));
+            }
+        }
+    }
+    rows
+}
+
+fn write_table<W: Write>(out: &mut W, report: &StatsReport) -> io::Result<()> {
+    let rows: Vec<_> = report_rows(report)
+        .into_iter()
+        .map(|(path, group, key, files, bytes)| {
+            let key = match (group, key) {
+                ("ext", "") => "(none)",
+                ("lang", "") => "(other)",
+                _ => key,
+            };
+            [
+                path.to_string(),
+                group.to_string(),
+                key.to_string(),
+                files.to_string(),
+                format_size_human(bytes),
+            ]
+        })
+        .collect();
+
+    let header = ["PATH", "GROUP", "KEY", "FILES", "BYTES"];
+    let mut widths = header.map(str::len);
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    let header = header.map(String::from);
+    for row in std::iter::once(&header).chain(&rows) {
+        writeln!(
+            out,
+            "{:<w0$}  {:<w1$}  {:<w2$}  {:>w3$}  {:>w4$}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            row[4],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+            w3 = widths[3],
+            w4 = widths[4],
+        )?;
+    }
+    Ok(())
+}
+
+fn write_csv<W: Write>(out: &mut W, report: &StatsReport) -> io::Result<()> {
+    writeln!(out, "path,group,key,files,bytes")?;
+    for (path, group, key, files, bytes) in report_rows(report) {
+        writeln!(
+            out,
+            "{},{group},{},{files},{bytes}",
+            csv_field(path),
+            csv_field(key)
+        )?;
+    }
+    Ok(())
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn stats_count_extensions_and_languages_per_directory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("app/Forms")).unwrap();
+        fs::create_dir_all(root.join("lib")).unwrap();
+        fs::write(root.join("app/Main.vb"), vec![0u8; 10]).unwrap();
+        fs::write(root.join("app/Forms/Form1.VB"), vec![0u8; 20]).unwrap();
+        fs::write(root.join("app/app.vbproj"), vec![0u8; 5]).unwrap();
+        fs::write(root.join("lib/Util.cs"), vec![0u8; 7]).unwrap();
+        fs::write(root.join("LICENSE"), vec![0u8; 1]).unwrap();
+
+        let cli = Cli::parse_from(["printree"]);
+        let report = collect_stats(&cli, root, 1).unwrap();
+        let paths: Vec<_> = report.scopes.iter().map(|s| s.path.as_str()).collect();
+        assert_eq!(paths, vec![".", "app", "lib"]);
+
+        let total = &report.scopes[0];
+        assert_eq!((total.files, total.bytes), (5, 43));
+        let app = &report.scopes[1];
+        assert_eq!((app.files, app.bytes), (3, 35));
+        let exts: Vec<_> = app
+            .extensions
+            .iter()
+            .map(|g| (g.name.as_deref(), g.files, g.bytes))
+            .collect();
+        assert_eq!(exts, vec![(Some("vb"), 2, 30), (Some("vbproj"), 1, 5)]);
+        assert_eq!(app.languages[0].name.as_deref(), Some("vb"));
+        assert_eq!(app.languages[0].files, 3);
+
+        let langs: Vec<_> = total
+            .languages
+            .iter()
+            .map(|g| (g.name.as_deref(), g.files))
+            .collect();
+        assert_eq!(langs, vec![(Some("vb"), 3), (Some("csharp"), 1), (None, 1)]);
+
+        let mut csv = Vec::new();
+        write_csv(&mut csv, &report).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        assert!(csv.contains("\nlib,ext,cs,1,7\n"));
+        assert!(csv.contains("\n.,ext,,1,1\n"));
+    }
+}
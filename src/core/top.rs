@@ -0,0 +1,302 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::cli::{Cli, GitignoreMode, TopBy, TopFormat};
+use crate::core::tree::{format_size_human, visit_tree, TreeItem};
+
+/// 上位 N 件の候補。`key` が大きいほど上位（`--by age` は古いほど大きくなるよう符号を反転）
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Ranked {
+    key: i128,
+    /// 同点はパスの昇順を上位にする
+    path: Reverse<PathBuf>,
+    size: Option<u64>,
+    mtime: Option<SystemTime>,
+}
+
+#[derive(Serialize)]
+struct JsonTop {
+    rank: usize,
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<u64>,
+}
+
+/// 走査中に N 件だけ保持する最小ヒープ
+struct TopHeap {
+    limit: usize,
+    heap: BinaryHeap<Reverse<Ranked>>,
+}
+
+impl TopHeap {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit,
+            heap: BinaryHeap::with_capacity(limit + 1),
+        }
+    }
+
+    fn push(&mut self, item: Ranked) {
+        if self.heap.len() == self.limit {
+            match self.heap.peek() {
+                Some(Reverse(lowest)) if *lowest >= item => return,
+                _ => {}
+            }
+        }
+        self.heap.push(Reverse(item));
+        if self.heap.len() > self.limit {
+            self.heap.pop();
+        }
+    }
+
+    /// 上位から順に
+    fn into_sorted(self) -> Vec<Ranked> {
+        let mut items: Vec<Ranked> = self.heap.into_iter().map(|Reverse(item)| item).collect();
+        items.sort_by(|a, b| b.cmp(a));
+        items
+    }
+}
+
+pub fn run_top(
+    cli: &Cli,
+    path: Option<&Path>,
+    by: TopBy,
+    n: usize,
+    tree: bool,
+    format: TopFormat,
+) -> Result<()> {
+    if n == 0 {
+        return Err(anyhow!("invalid --n value: must be at least 1"));
+    }
+    if matches!(cli.gitignore, GitignoreMode::On) {
+        eprintln!("[warn] --gitignore on ignored by top");
+    }
+    if tree && format != TopFormat::Plain {
+        eprintln!("[warn] --tree only applies to --format plain");
+    }
+
+    let root = path
+        .map(Path::to_path_buf)
+        .or_else(|| cli.path.clone())
+        .unwrap_or_else(|| PathBuf::from("."));
+    let items = collect_top(cli, &root, by, n)?;
+
+    let mut stdout = BufWriter::new(io::stdout().lock());
+    if format == TopFormat::Json {
+        let records: Vec<JsonTop> = items
+            .iter()
+            .enumerate()
+            .map(|(idx, item)| JsonTop {
+                rank: idx + 1,
+                path: item.path.0.display().to_string(),
+                size: item.size,
+                mtime: item.mtime.and_then(unix_secs),
+            })
+            .collect();
+        serde_json::to_writer_pretty(&mut stdout, &records)?;
+        writeln!(&mut stdout)?;
+    } else {
+        write_list(&mut stdout, &items)?;
+        if tree && !items.is_empty() {
+            writeln!(&mut stdout)?;
+            write_location_tree(&mut stdout, &root, &items, by)?;
+        }
+    }
+    stdout.flush()?;
+    Ok(())
+}
+
+fn collect_top(cli: &Cli, root: &Path, by: TopBy, n: usize) -> Result<Vec<Ranked>> {
+    let mut heap = TopHeap::new(n);
+    visit_tree(cli, root, |item: &TreeItem<'_>| {
+        if !item.is_file {
+            return;
+        }
+        let key = match by {
+            TopBy::Size => item.size.map(i128::from),
+            TopBy::Mtime => item.mtime.and_then(signed_secs),
+            TopBy::Age => item.mtime.and_then(signed_secs).map(|secs| -secs),
+        };
+        if let Some(key) = key {
+            heap.push(Ranked {
+                key,
+                path: Reverse(item.path.to_path_buf()),
+                size: item.size,
+                mtime: item.mtime,
+            });
+        }
+    })?;
+    Ok(heap.into_sorted())
+}
+
+fn signed_secs(time: SystemTime) -> Option<i128> {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => Some(i128::from(d.as_secs())),
+        Err(e) => Some(-i128::from(e.duration().as_secs())),
+    }
+}
+
+fn unix_secs(time: SystemTime) -> Option<u64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs())
+}
+
+fn format_mtime(time: Option<SystemTime>) -> String {
+    time.map(|t| {
+        DateTime::<Local>::from(t)
+            .format("%Y-%m-%d %H:%M")
+            .to_string()
+    })
+    .unwrap_or_else(|| String::from("-"))
+}
+
+fn write_list<W: Write>(out: &mut W, items: &[Ranked]) -> io::Result<()> {
+    let sizes: Vec<String> = items
+        .iter()
+        .map(|item| item.size.map(format_size_human).unwrap_or_default())
+        .collect();
+    let rank_width = items.len().to_string().len();
+    let size_width = sizes.iter().map(String::len).max().unwrap_or(0);
+    for (idx, (item, size)) in items.iter().zip(&sizes).enumerate() {
+        writeln!(
+            out,
+            "{:>rank_width$}. {:>size_width$}  {}  {}",
+            idx + 1,
+            size,
+            format_mtime(item.mtime),
+            item.path.0.display()
+        )?;
+    }
+    Ok(())
+}
+
+/// 一覧に出たファイルの場所だけを枝にした小さなツリー
+#[derive(Default)]
+struct LocationNode {
+    children: BTreeMap<String, LocationNode>,
+    rank: Option<usize>,
+}
+
+fn write_location_tree<W: Write>(
+    out: &mut W,
+    root: &Path,
+    items: &[Ranked],
+    by: TopBy,
+) -> io::Result<()> {
+    let mut tree = LocationNode::default();
+    for (idx, item) in items.iter().enumerate() {
+        let rel = item.path.0.strip_prefix(root).unwrap_or(&item.path.0);
+        let mut node = &mut tree;
+        for component in rel.components() {
+            let name = component.as_os_str().to_string_lossy().into_owned();
+            node = node.children.entry(name).or_default();
+        }
+        node.rank = Some(idx);
+    }
+
+    writeln!(out, "{}", root.display())?;
+    write_location_children(out, &tree, "", items, by)
+}
+
+fn write_location_children<W: Write>(
+    out: &mut W,
+    node: &LocationNode,
+    prefix: &str,
+    items: &[Ranked],
+    by: TopBy,
+) -> io::Result<()> {
+    let len = node.children.len();
+    for (idx, (name, child)) in node.children.iter().enumerate() {
+        let is_last = idx + 1 == len;
+        let connector = if is_last { "└── " } else { "├── " };
+        write!(out, "{prefix}{connector}{name}")?;
+        if let Some(rank) = child.rank {
+            let item = &items[rank];
+            let value = match by {
+                TopBy::Size => item.size.map(format_size_human).unwrap_or_default(),
+                TopBy::Mtime | TopBy::Age => format_mtime(item.mtime),
+            };
+            write!(out, "  [#{} {}]", rank + 1, value)?;
+        }
+        writeln!(out)?;
+        let child_prefix = format!("{prefix}{}", if is_last { "    " } else { "│   " });
+        write_location_children(out, child, &child_prefix, items, by)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use std::fs;
+
+    #[test]
+    fn top_keeps_only_the_largest_entries_and_draws_their_locations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("logs/old")).unwrap();
+        fs::write(root.join("a.bin"), vec![0u8; 50]).unwrap();
+        fs::write(root.join("b.bin"), vec![0u8; 50]).unwrap();
+        fs::write(root.join("logs/app.log"), vec![0u8; 300]).unwrap();
+        fs::write(root.join("logs/old/app.1.log"), vec![0u8; 200]).unwrap();
+        fs::write(root.join("small.txt"), vec![0u8; 1]).unwrap();
+
+        let cli = Cli::parse_from(["printree"]);
+        let items = collect_top(&cli, root, TopBy::Size, 3).unwrap();
+        let names: Vec<_> = items
+            .iter()
+            .map(|item| item.path.0.strip_prefix(root).unwrap().to_path_buf())
+            .collect();
+        // 同点はパスの昇順
+        assert_eq!(
+            names,
+            vec![
+                PathBuf::from("logs/app.log"),
+                PathBuf::from("logs/old/app.1.log"),
+                PathBuf::from("a.bin"),
+            ]
+        );
+
+        let mut out = Vec::new();
+        write_location_tree(&mut out, root, &items, TopBy::Size).unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = text.lines().skip(1).collect();
+        assert_eq!(
+            lines,
+            vec![
+                "├── a.bin  [#3 50 B]",
+                "└── logs",
+                "    ├── app.log  [#1 300 B]",
+                "    └── old",
+                "        └── app.1.log  [#2 200 B]",
+            ]
+        );
+    }
+
+    #[test]
+    fn top_heap_orders_by_age_with_negated_keys() {
+        let mut heap = TopHeap::new(2);
+        for (secs, name) in [(300, "c"), (100, "a"), (200, "b")] {
+            heap.push(Ranked {
+                key: -secs,
+                path: Reverse(PathBuf::from(name)),
+                size: None,
+                mtime: None,
+            });
+        }
+        let names: Vec<_> = heap
+            .into_sorted()
+            .into_iter()
+            .map(|item| item.path.0)
+            .collect();
+        assert_eq!(names, vec![PathBuf::from("a"), PathBuf::from("b")]);
+    }
+}
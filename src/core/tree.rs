@@ -1,113 +1,123 @@
 use encoding_rs::{Encoding, SHIFT_JIS, UTF_16LE};
-use std::collections::{HashMap, HashSet};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::env;
 use std::ffi::OsString;
 use std::fs::{self, FileType, Metadata};
-use std::io::{self, BufWriter, Write};
+use std::io::{self, BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
-use std::sync::mpsc;
+use std::sync::{mpsc, Arc};
 use std::thread;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use anyhow::{anyhow, Result};
-use git2::{ErrorCode, Repository, Status, StatusOptions};
-use regex_automata::meta::Regex;
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use git2::{
+    Commit, DiffDelta, DiffOptions, ErrorCode, Oid, Patch, Repository, Sort, Status, StatusOptions,
+    SubmoduleIgnore, SubmoduleStatus, TreeWalkMode, TreeWalkResult,
+};
 use serde::Serialize;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use walkdir::WalkDir;
 
-use crate::cli::{Cli, Format, MatchMode, SortMode};
-use crate::utils::{
-    allow_type, build_include_prefixes, build_patterns, color_choice, include_dir_allowed,
-    is_hidden, PatternList,
-};
+use crate::cli::{Cli, Cmd, Collation, Format, SortMode, Source, TypeFilter};
+use crate::core::churn::{ChurnIndex, ChurnStats};
+use crate::utils::{allow_kind, allow_type, color_choice, is_hidden, Matcher};
 
 #[cfg(unix)]
 use std::os::unix::fs::PermissionsExt;
 #[cfg(windows)]
 use std::os::windows::fs::MetadataExt;
 
+/// 降りる深さの上限。`--summary-depth N` はルート直下を 1 として深さ N のディレクトリまで表示する
+fn print_depth_limit(cli: &Cli) -> Option<usize> {
+    match (cli.max_depth, cli.summary_depth) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+/// ルートだけを出力するか（`--max-depth 1` または `--summary-depth 0`）
+fn root_only(cli: &Cli) -> bool {
+    matches!(cli.max_depth, Some(1)) || matches!(cli.summary_depth, Some(0))
+}
+
+/// ツリー出力が最後まで書けたか、出力上限で打ち切ったか
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Completion {
+    Complete,
+    Truncated,
+}
+
+/// 走査に使う設定・フィルタ・git の情報。ディレクトリを読む関数はこれをまとめて受け取る
+#[derive(Clone, Copy)]
+struct WalkContext<'a> {
+    root: &'a Path,
+    cli: &'a Cli,
+    matcher: &'a Matcher,
+    filters: &'a Filters,
+    git: &'a GitTracker,
+    jobs: &'a JobPool,
+}
+
 /// ディレクトリツリーのメイン実行関数
-pub fn run_tree(cli: &Cli) -> Result<()> {
+pub fn run_tree(cli: &Cli) -> Result<Completion> {
     let root = cli.path.clone().unwrap_or_else(|| PathBuf::from("."));
-    let include_glob = build_patterns(&cli.includes, cli.pattern_syntax, true)?;
-    let exclude_glob = build_patterns(&cli.excludes, cli.pattern_syntax, false)?;
-    let include_prefixes =
-        build_include_prefixes(&root, &cli.includes, cli.pattern_syntax, cli.match_mode);
-    let filters = Filters::from_cli(cli, &root)?;
-    let git = GitTracker::prepare(&root, cli)?;
+    run_tree_at(&root, cli, None)
+}
+
+/// `printree churn` からも使う本体。`churn` があれば各エントリに履歴の集計を添える
+pub(crate) fn run_tree_at(root: &Path, cli: &Cli, churn: Option<ChurnIndex>) -> Result<Completion> {
+    let matcher = Matcher::from_cli(cli, root)?;
+    let mut filters = Filters::from_cli(cli, root)?;
+    let mut git = GitTracker::prepare(root, cli)?;
+    git.churn = churn;
+    filters.use_git(cli, root, &git);
     let jobs = JobPool::new(cli)?;
+    if cli.bfs && !matches!(cli.format, Format::Ndjson | Format::Csv) {
+        eprintln!("[warn] --bfs only applies to --format ndjson/csv");
+    }
+    if cli.stats {
+        if !matches!(
+            cli.format,
+            Format::Plain | Format::Json | Format::Ndjson | Format::Toon
+        ) {
+            eprintln!("[warn] --stats only applies to --format plain/json/ndjson/toon");
+        } else if cli.limit_output_bytes.is_some() {
+            eprintln!("[warn] --stats ignored with --limit-output-bytes");
+        }
+    }
+    if cli.summary_depth.is_some() {
+        let summary = SummaryIndex::build(&WalkContext {
+            root,
+            cli,
+            matcher: &matcher,
+            filters: &filters,
+            git: &git,
+            jobs: &jobs,
+        })?;
+        filters.summary = Some(summary);
+    }
+
+    let ctx = WalkContext {
+        root,
+        cli,
+        matcher: &matcher,
+        filters: &filters,
+        git: &git,
+        jobs: &jobs,
+    };
 
     match cli.format {
-        Format::Json => run_tree_json(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Plain => run_tree_plain(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Ndjson => run_tree_ndjson(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Csv => run_tree_csv(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Yaml => run_tree_yaml(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Html => run_tree_html(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
-        Format::Toon => run_tree_toon(
-            &root,
-            cli,
-            &include_glob,
-            &include_prefixes,
-            &exclude_glob,
-            &filters,
-            &git,
-            &jobs,
-        ),
+        Format::Json => run_tree_json(&ctx),
+        Format::Plain => run_tree_plain(&ctx),
+        Format::Ndjson => run_tree_ndjson(&ctx),
+        Format::Csv => run_tree_csv(&ctx),
+        Format::Yaml => run_tree_yaml(&ctx),
+        Format::Html => run_tree_html(&ctx),
+        Format::Toon => run_tree_toon(&ctx),
     }
 }
 
@@ -119,6 +129,8 @@ struct EntryMeta {
     target_file_type: Option<FileType>,
     size: Option<u64>,
     mtime: Option<SystemTime>,
+    ctime: Option<SystemTime>,
+    atime: Option<SystemTime>,
     perm_unix: Option<u32>,
     #[cfg_attr(not(windows), allow(dead_code))]
     perm_win: Option<u32>,
@@ -127,7 +139,29 @@ struct EntryMeta {
     canonical_path: Option<PathBuf>,
     loop_detected: bool,
     error: Option<String>,
-    git_status: Option<char>,
+    git_status: Option<GitStatus>,
+    /// 配下の変更件数（ディレクトリのみ）
+    git_changes: Option<GitRollup>,
+    /// `--git-rename` で検出したリネーム元
+    rename: Option<GitRename>,
+    /// サブモジュールのディレクトリなら、その状態
+    submodule: Option<GitSubmodule>,
+    /// `--lfs` で見つけた Git LFS のポインタ。`size` は実体の大きさに置き換える
+    lfs: Option<LfsPointer>,
+    /// `--git-log` で引いた最後のコミット
+    last_commit: Option<Arc<GitCommitInfo>>,
+    /// `printree churn` の変更頻度
+    churn: Option<ChurnStats>,
+    /// ファイルシステムではなく git のモードから決めた種類（`--git-ghosts` / `--rev` / `--source index`）
+    git_kind: Option<EntryKind>,
+    /// `--source index` でのインデックスのステージ番号（0 = 通常、1〜3 = 衝突中）
+    stage: Option<u8>,
+    /// `--git-ghosts` で差し込んだ削除済みエントリ
+    ghost: bool,
+    /// `--max-entries-per-dir` で省略したエントリ数（要約用の合成エントリのみ）
+    truncated: Option<usize>,
+    /// `--summary-depth` 用の配下の集計（ディレクトリのみ）
+    totals: Option<DirTotals>,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -148,7 +182,33 @@ struct Entry {
     #[serde(skip_serializing_if = "Option::is_none")]
     error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
-    git_status: Option<char>,
+    git_status: Option<GitStatus>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    git_changes: Option<GitRollup>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    renamed_from: Option<String>,
+    /// リネーム前後の類似度（%）。内容を行単位で比べた一致率で、libgit2 がリネーム判定
+    /// （`--rename-threshold`）に使うスコアの近似。git2 からは libgit2 のスコアを読めない
+    #[serde(skip_serializing_if = "Option::is_none")]
+    similarity: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    submodule: Option<GitSubmodule>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    ghost: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stage: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_commit: Option<GitCommitInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    churn: Option<ChurnStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lfs: Option<LfsPointer>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    truncated: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    files: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dirs: Option<u64>,
 }
 
 #[derive(Clone, Copy, Debug, Serialize)]
@@ -158,6 +218,10 @@ enum EntryKind {
     Dir,
     Symlink,
     Unknown,
+    /// `--max-entries-per-dir` で省略したエントリの要約
+    Truncated,
+    /// `--limit-entries` / `--limit-output-bytes` で出力全体を打ち切った印（CSV の末尾行）
+    Budget,
 }
 
 struct Frame {
@@ -172,16 +236,19 @@ struct PlainPending {
     prefix: String,
     is_last: bool,
     total_size: u64,
+    /// 出力上限用に見積もった行のバイト数（集計サイズが未確定なので最大桁で数える）
+    reserved: usize,
 }
 
 impl PlainPending {
-    fn new(mut entry: Entry, prefix: String, is_last: bool) -> Self {
+    fn new(mut entry: Entry, prefix: String, is_last: bool, reserved: usize) -> Self {
         entry.size = None;
         Self {
             entry,
             prefix,
             is_last,
             total_size: 0,
+            reserved,
         }
     }
 
@@ -222,12 +289,155 @@ struct EntrySeed {
 
 struct GitTracker {
     map: Option<GitStatusMap>,
+    log: Option<GitLogIndex>,
+    /// `printree churn` の集計
+    churn: Option<ChurnIndex>,
+    /// `--rev` / `--source index` で辿る git 上のツリー
+    snapshot: Option<GitSnapshot>,
+    /// `--lfs` の実体の置き場所
+    lfs: Option<LfsStore>,
+}
+
+/// Git LFS のポインタファイルが指す実体
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct LfsPointer {
+    /// sha256 の 16 進表記
+    oid: String,
+    /// 実体の大きさ
+    size: u64,
+    /// `.git/lfs/objects` に実体を取得済みか
+    local: bool,
+}
+
+/// `--lfs` 用。ポインタの判定と、実体を取得済みかの確認に使う
+#[derive(Clone)]
+struct LfsStore {
+    /// `<共通の .git>/lfs/objects`。リポジトリの外なら None
+    objects: Option<PathBuf>,
+}
+
+/// LFS の仕様上、ポインタファイルはこれより大きくならない
+const LFS_POINTER_MAX: u64 = 1024;
+const LFS_VERSION_PREFIX: &[u8] = b"version https://git-lfs.github.com/spec/";
+
+/// `--rev` / `--source index` 用。ファイルシステムの代わりに git のツリーを辿る
+struct GitSnapshot {
+    repo: Repository,
+    source: SnapshotSource,
+    lfs: Option<LfsStore>,
+    workdir: PathBuf,
+    cwd: PathBuf,
+}
+
+enum SnapshotSource {
+    /// `--rev` のコミットのツリー
+    Tree(Oid),
+    /// `--source index` のインデックス
+    Index(IndexListing),
+}
+
+/// インデックスのエントリをディレクトリ（ワークツリー相対）ごとにまとめたもの。
+/// 衝突中のパスはステージ 1〜3 が別々のエントリになる
+struct IndexListing {
+    dirs: HashMap<PathBuf, BTreeMap<(OsString, u8), IndexItem>>,
+}
+
+struct IndexItem {
+    mode: i32,
+    id: Oid,
+    /// インデックスに無い中間ディレクトリは None
+    stage: Option<u8>,
+}
+
+/// インデックスのエントリのフラグのうちステージ番号のビット
+const GIT_INDEX_STAGE_MASK: u16 = 0x3000;
+const GIT_INDEX_STAGE_SHIFT: u16 = 12;
+
+const GIT_MODE_TYPE_MASK: i32 = 0o170000;
+const GIT_MODE_TREE: i32 = 0o040000;
+const GIT_MODE_SYMLINK: i32 = 0o120000;
+const GIT_MODE_SUBMODULE: i32 = 0o160000;
+
+/// `--git-log` 用。HEAD から 1 回だけ履歴を辿り、パスごとに最後に触れたコミットを引けるようにしたもの
+struct GitLogIndex {
+    workdir: PathBuf,
+    cwd: PathBuf,
+    /// ワークツリー相対のパス（ディレクトリと、ルートの空パスを含む）からコミットへの対応
+    paths: HashMap<PathBuf, Arc<GitCommitInfo>>,
+}
+
+/// エントリに添える最後のコミット
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct GitCommitInfo {
+    hash: String,
+    author: String,
+    /// コミット日時（RFC 3339）
+    date: String,
+    /// 実行時点からの経過（`3 days ago` など）
+    relative: String,
+    subject: String,
 }
 
 struct GitStatusMap {
     workdir: PathBuf,
     cwd: PathBuf,
-    statuses: HashMap<PathBuf, char>,
+    statuses: HashMap<PathBuf, GitStatus>,
+    /// `!!` の無視ディレクトリ。libgit2 は中身を列挙しないので、配下は祖先から引き継ぐ
+    ignored_dirs: HashSet<PathBuf>,
+    /// ディレクトリごとの配下の変更件数（ワークツリー相対、ルートは空パス）
+    rollups: HashMap<PathBuf, GitRollup>,
+    /// リネーム先（ワークツリー相対）からリネーム元への対応
+    renames: HashMap<PathBuf, GitRename>,
+    /// `--git-ghosts` 用。ディレクトリ（ワークツリー相対）ごとの削除済みの子と種類
+    ghosts: HashMap<PathBuf, BTreeMap<OsString, EntryKind>>,
+    /// サブモジュールのパス（ワークツリー相対）ごとの状態
+    submodules: HashMap<PathBuf, GitSubmodule>,
+}
+
+/// サブモジュール自身の状態。親のステータスでは ` M` としか分からないので別に持つ
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize)]
+struct GitSubmodule {
+    /// `git submodule update --init` 前で中身が無い
+    uninitialized: bool,
+    /// チェックアウトしているコミットが親に記録したコミットと違う
+    new_commits: bool,
+    /// 中に未コミットの変更か未追跡のファイルがある
+    dirty: bool,
+}
+
+/// `git status` の結果を集める。`--git-submodules` ではサブモジュールと入れ子のリポジトリにも潜り、
+/// パスを外側のワークツリー相対に直して同じ表に入れる
+#[derive(Default)]
+struct StatusCollector {
+    statuses: HashMap<PathBuf, GitStatus>,
+    ignored_dirs: HashSet<PathBuf>,
+    renames: HashMap<PathBuf, GitRename>,
+    submodules: HashMap<PathBuf, GitSubmodule>,
+}
+
+/// リネーム元のパス（ワークツリー相対）と類似度
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct GitRename {
+    from: PathBuf,
+    /// 行単位で数えた一致率（%）。git の類似度スコアの近似で、読めなければ None
+    similarity: Option<u8>,
+}
+
+/// 配下の変更を記号ごとに数えたもの。plain では `[M3 A1]` のように出す
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct GitRollup {
+    counts: [u32; GIT_ROLLUP_ORDER.len()],
+}
+
+/// 集計で数える記号と表示順
+const GIT_ROLLUP_ORDER: [char; 7] = ['M', 'A', 'D', 'R', 'T', 'U', '?'];
+
+/// `git status --short` と同じ 2 桁の状態。X がインデックス（ステージ済み）、
+/// Y がワークツリー（未ステージ）で、変更なしの桁は空白
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+struct GitStatus {
+    index: char,
+    worktree: char,
 }
 
 struct JobPool {
@@ -260,324 +470,2040 @@ impl JobPool {
     }
 }
 
-impl GitTracker {
-    fn prepare(root: &Path, cli: &Cli) -> Result<Self> {
-        let want_status = cli.git_status || cli.git_rename;
-        if !want_status {
-            return Ok(Self { map: None });
+/// `--limit-entries` / `--limit-output-bytes` の残量。
+/// 各形式は書き出す前にエントリのバイト数を申告し、断られたら走査を打ち切って
+/// 閉じ括弧と末尾の要約（トレーラ）だけを書く。トレーラ分は先に確保しておく。
+struct OutputBudget {
+    max_entries: Option<usize>,
+    max_bytes: Option<usize>,
+    entries: usize,
+    bytes: usize,
+    reserved: usize,
+    hit: Option<BudgetLimit>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum BudgetLimit {
+    Entries(usize),
+    Bytes(usize),
+}
+
+impl BudgetLimit {
+    fn reason(self) -> &'static str {
+        match self {
+            BudgetLimit::Entries(_) => "limit-entries",
+            BudgetLimit::Bytes(_) => "limit-output-bytes",
         }
+    }
 
-        if cli.git_rename {
-            eprintln!("[warn] rename detection enabled (slow)");
+    fn value(self) -> usize {
+        match self {
+            BudgetLimit::Entries(n) | BudgetLimit::Bytes(n) => n,
         }
+    }
 
-        let cwd = env::current_dir()?;
-        let repo = match Repository::discover(root) {
-            Ok(repo) => repo,
-            Err(err) if err.code() == ErrorCode::NotFound => {
-                if cli.git_status || cli.git_rename {
-                    eprintln!("[warn] --git-status ignored: .git not found");
-                }
-                return Ok(Self { map: None });
-            }
-            Err(err) => return Err(anyhow!(err)),
-        };
+    fn describe(self) -> String {
+        format!("--{} {}", self.reason(), self.value())
+    }
+}
 
-        let workdir = match repo.workdir() {
-            Some(dir) => dir.to_path_buf(),
-            None => {
-                eprintln!("[warn] --git-status ignored: repository has no workdir");
-                return Ok(Self { map: None });
-            }
-        };
+/// JSON/NDJSON の末尾に置く打ち切りレコード
+#[derive(Serialize)]
+struct TruncationRecord {
+    kind: &'static str,
+    reason: &'static str,
+    limit: usize,
+    entries: usize,
+}
 
-        let mut opts = StatusOptions::new();
-        opts.include_untracked(true)
-            .recurse_untracked_dirs(true)
-            .include_ignored(false)
-            .include_unreadable(true);
-        if cli.git_rename {
-            opts.renames_head_to_index(true);
-            opts.renames_index_to_workdir(true);
-            opts.renames_from_rewrites(true);
+impl OutputBudget {
+    fn new(cli: &Cli) -> Self {
+        Self {
+            max_entries: cli.limit_entries,
+            max_bytes: cli.limit_output_bytes,
+            entries: 0,
+            bytes: 0,
+            reserved: 0,
+            hit: None,
         }
+    }
 
-        let statuses = repo.statuses(Some(&mut opts))?;
-        let mut map = HashMap::new();
-        for entry in statuses.iter() {
-            if let Some(symbol) = git_status_symbol(entry.status()) {
-                if let Some(path) = status_entry_path(&entry) {
-                    update_git_status(&mut map, path, symbol);
-                }
-            }
-        }
+    /// バイト数の計測が必要か。不要なら各形式は申告用のレンダリングを省く
+    fn tracks_bytes(&self) -> bool {
+        self.max_bytes.is_some()
+    }
 
-        Ok(Self {
-            map: Some(GitStatusMap {
-                workdir,
-                cwd,
-                statuses: map,
-            }),
-        })
+    /// 打ち切りが起こり得るときのトレーラ候補（最悪ケースの確保量の計算用）
+    fn possible_limits(&self) -> Vec<BudgetLimit> {
+        let mut limits = Vec::new();
+        limits.extend(self.max_entries.map(BudgetLimit::Entries));
+        limits.extend(self.max_bytes.map(BudgetLimit::Bytes));
+        limits
     }
 
-    fn apply(&self, meta: &mut EntryMeta) {
-        if let Some(map) = &self.map {
-            meta.git_status = map.status_for(&meta.path);
-        }
+    /// ルートや見出しなど、必ず書く部分
+    fn charge_fixed(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_add(bytes);
     }
-}
 
-impl GitStatusMap {
-    fn status_for(&self, path: &Path) -> Option<char> {
-        let abs = if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            self.cwd.join(path)
-        };
-        let rel = abs.strip_prefix(&self.workdir).ok()?;
-        if rel.as_os_str().is_empty() {
-            return None;
-        }
-        self.statuses.get(rel).copied()
+    /// 閉じ括弧やトレーラのために先に確保しておく
+    fn reserve(&mut self, bytes: usize) {
+        self.reserved = self.reserved.saturating_add(bytes);
     }
-}
 
-fn status_entry_path(entry: &git2::StatusEntry<'_>) -> Option<PathBuf> {
-    if let Some(delta) = entry.index_to_workdir() {
-        if let Some(path) = delta.new_file().path() {
-            return Some(path.to_path_buf());
+    /// エントリ 1 件を書いてよいか。断った時点で打ち切り扱いになる
+    fn admit(&mut self, bytes: usize) -> bool {
+        if self.hit.is_some() {
+            return false;
         }
-    }
-    if let Some(delta) = entry.head_to_index() {
-        if let Some(path) = delta.new_file().path() {
-            return Some(path.to_path_buf());
+        if let Some(max) = self.max_entries {
+            if self.entries >= max {
+                self.hit = Some(BudgetLimit::Entries(max));
+                return false;
+            }
         }
-        if let Some(path) = delta.old_file().path() {
-            return Some(path.to_path_buf());
+        if let Some(max) = self.max_bytes {
+            if self.bytes + self.reserved + bytes > max {
+                self.hit = Some(BudgetLimit::Bytes(max));
+                return false;
+            }
         }
+        self.entries += 1;
+        self.bytes += bytes;
+        true
     }
-    entry.path().map(PathBuf::from)
-}
 
-fn git_status_symbol(status: Status) -> Option<char> {
-    if status.is_wt_deleted() || status.is_index_deleted() {
-        Some('D')
-    } else if status.is_wt_renamed() || status.is_index_renamed() {
-        Some('R')
-    } else if status.is_wt_new() || status.is_index_new() {
-        Some('A')
-    } else if status.is_wt_modified()
-        || status.is_index_modified()
-        || status.is_wt_typechange()
-        || status.is_index_typechange()
-    {
-        Some('M')
-    } else {
-        None
+    /// 見積もりより実際の出力が短かった分を戻す
+    fn refund(&mut self, bytes: usize) {
+        self.bytes = self.bytes.saturating_sub(bytes);
     }
-}
 
-fn update_git_status(map: &mut HashMap<PathBuf, char>, path: PathBuf, status: char) {
-    match map.entry(path) {
-        std::collections::hash_map::Entry::Occupied(mut occ) => {
-            if git_status_priority(status) > git_status_priority(*occ.get()) {
-                occ.insert(status);
-            }
-        }
-        std::collections::hash_map::Entry::Vacant(vac) => {
-            vac.insert(status);
+    fn exhausted(&self) -> Option<BudgetLimit> {
+        self.hit
+    }
+
+    fn record(&self, limit: BudgetLimit) -> TruncationRecord {
+        TruncationRecord {
+            kind: "budget",
+            reason: limit.reason(),
+            limit: limit.value(),
+            entries: self.entries,
         }
     }
-}
 
-fn git_status_priority(symbol: char) -> u8 {
-    match symbol {
-        'D' => 4,
-        'R' => 3,
-        'A' => 2,
-        'M' => 1,
-        _ => 0,
+    /// 最悪ケース（件数の桁が最大）のトレーラ長
+    fn worst_trailer(&self, render: impl Fn(&TruncationRecord, BudgetLimit) -> usize) -> usize {
+        self.possible_limits()
+            .into_iter()
+            .map(|limit| {
+                let record = TruncationRecord {
+                    entries: usize::MAX,
+                    ..self.record(limit)
+                };
+                render(&record, limit)
+            })
+            .max()
+            .unwrap_or(0)
     }
-}
 
-struct Filters {
-    root: PathBuf,
-    match_mode: MatchMode,
-    regex: Option<Regex>,
-    size: Option<SizeFilter>,
-    mtime: Option<MtimeFilter>,
-    perm: Option<PermFilter>,
+    fn completion(&self) -> Completion {
+        if self.hit.is_some() {
+            Completion::Truncated
+        } else {
+            Completion::Complete
+        }
+    }
 }
 
-#[derive(Clone, Copy)]
-enum SizeCmp {
-    Lt,
-    Le,
-    Eq,
-    Ge,
-    Gt,
+/// 出力したエントリ（ルートを除く）の集計。plain のフッタと `--stats` に使う
+struct TreeStats {
+    started: Instant,
+    directories: u64,
+    files: u64,
+    symlinks: u64,
+    bytes: u64,
+    loops: u64,
+    errors: u64,
+    omitted: u64,
+    largest: Vec<SizedPath>,
+    extensions: HashMap<String, u64>,
+    deepest: Option<DeepestPath>,
 }
 
-struct SizeFilter {
-    cmp: SizeCmp,
-    threshold: u64,
-}
+/// `--stats` で挙げる大きいファイルの件数
+const STATS_LARGEST: usize = 5;
 
-struct MtimeFilter {
-    earliest: SystemTime,
+#[derive(Clone, Debug, Serialize)]
+struct SizedPath {
+    path: String,
+    size: u64,
 }
 
-struct PermFilter {
-    expected: u32,
+#[derive(Clone, Debug, Serialize)]
+struct DeepestPath {
+    path: String,
+    depth: usize,
 }
 
-impl Filters {
-    fn from_cli(cli: &Cli, root: &Path) -> Result<Self> {
-        let regex = if let Some(pattern) = cli.filter_regex.as_deref() {
-            Some(
-                Regex::new(pattern)
-                    .map_err(|err| anyhow!("invalid --filter-regex value: {err}"))?,
-            )
-        } else {
-            None
-        };
-
-        let size = if let Some(spec) = cli.filter_size.as_deref() {
-            Some(parse_size_filter(spec)?)
-        } else {
-            None
-        };
-
-        let mtime = if let Some(spec) = cli.filter_mtime.as_deref() {
-            Some(parse_mtime_filter(spec)?)
-        } else {
-            None
-        };
+#[derive(Debug, Serialize)]
+struct ExtensionCount {
+    /// 拡張子なしは null
+    ext: Option<String>,
+    count: u64,
+}
 
-        let perm = if let Some(spec) = cli.filter_perm.as_deref() {
-            parse_perm_filter(spec)?
-        } else {
-            None
-        };
+/// JSON/NDJSON の末尾に置く `--stats` レコード
+#[derive(Debug, Serialize)]
+struct StatsRecord {
+    kind: &'static str,
+    directories: u64,
+    files: u64,
+    symlinks: u64,
+    bytes: u64,
+    loops: u64,
+    errors: u64,
+    omitted: u64,
+    largest: Vec<SizedPath>,
+    extensions: Vec<ExtensionCount>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deepest: Option<DeepestPath>,
+    elapsed_ms: u64,
+}
 
-        Ok(Self {
-            root: root.to_path_buf(),
-            match_mode: cli.match_mode,
-            regex,
-            size,
-            mtime,
-            perm,
-        })
+impl TreeStats {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            directories: 0,
+            files: 0,
+            symlinks: 0,
+            bytes: 0,
+            loops: 0,
+            errors: 0,
+            omitted: 0,
+            largest: Vec::new(),
+            extensions: HashMap::new(),
+            deepest: None,
+        }
     }
 
-    fn allows(&self, meta: &EntryMeta) -> bool {
-        if let Some(re) = &self.regex {
-            let target = match self.match_mode {
-                MatchMode::Name => meta.name.to_string_lossy().into_owned(),
-                MatchMode::Path => meta
-                    .path
-                    .strip_prefix(&self.root)
-                    .unwrap_or(&meta.path)
-                    .display()
-                    .to_string(),
-            };
-            if !re.is_match(target.as_str()) {
-                return false;
-            }
+    /// 種別ごとに数える。シンボリックリンクはファイルにもディレクトリにも含めない
+    fn record(&mut self, entry: &Entry) {
+        if entry.loop_detected {
+            self.loops += 1;
         }
-
-        if let Some(size) = &self.size {
-            if !size.allows(meta.size) {
-                return false;
-            }
+        if entry.error.is_some() {
+            self.errors += 1;
         }
-
-        if let Some(mtime) = &self.mtime {
-            if !mtime.allows(meta.mtime) {
-                return false;
+        match entry.kind {
+            EntryKind::Truncated => {
+                self.omitted += entry.truncated.unwrap_or(0) as u64;
+                return;
+            }
+            EntryKind::Budget => return,
+            EntryKind::Dir => self.directories += 1,
+            EntryKind::Symlink => self.symlinks += 1,
+            EntryKind::File | EntryKind::Unknown => {
+                self.files += 1;
+                let size = entry.size.unwrap_or(0);
+                self.bytes = self.bytes.saturating_add(size);
+                self.record_largest(&entry.path, size);
+                let ext = Path::new(&entry.name)
+                    .extension()
+                    .map(|ext| ext.to_string_lossy().to_lowercase())
+                    .unwrap_or_default();
+                *self.extensions.entry(ext).or_default() += 1;
             }
         }
+        if self.deepest.as_ref().is_none_or(|d| entry.depth > d.depth) {
+            self.deepest = Some(DeepestPath {
+                path: entry.path.clone(),
+                depth: entry.depth,
+            });
+        }
+    }
 
-        if let Some(perm) = &self.perm {
-            if !perm.allows(meta.perm_unix) {
-                return false;
-            }
+    fn record_largest(&mut self, path: &str, size: u64) {
+        if self.largest.len() == STATS_LARGEST
+            && self.largest.last().is_some_and(|last| last.size >= size)
+        {
+            return;
         }
+        let pos = self.largest.partition_point(|item| item.size >= size);
+        self.largest.insert(
+            pos,
+            SizedPath {
+                path: path.to_string(),
+                size,
+            },
+        );
+        self.largest.truncate(STATS_LARGEST);
+    }
 
-        true
+    /// 件数の多い順（同数なら拡張子順）
+    fn extension_counts(&self) -> Vec<ExtensionCount> {
+        let mut counts: Vec<ExtensionCount> = self
+            .extensions
+            .iter()
+            .map(|(ext, count)| ExtensionCount {
+                ext: (!ext.is_empty()).then(|| ext.clone()),
+                count: *count,
+            })
+            .collect();
+        counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.ext.cmp(&b.ext)));
+        counts
     }
-}
 
-impl SizeFilter {
-    fn allows(&self, size: Option<u64>) -> bool {
-        let Some(size) = size else {
-            return false;
-        };
-        match self.cmp {
-            SizeCmp::Lt => size < self.threshold,
-            SizeCmp::Le => size <= self.threshold,
-            SizeCmp::Eq => size == self.threshold,
-            SizeCmp::Ge => size >= self.threshold,
-            SizeCmp::Gt => size > self.threshold,
+    fn record_for_output(&self) -> StatsRecord {
+        StatsRecord {
+            kind: "stats",
+            directories: self.directories,
+            files: self.files,
+            symlinks: self.symlinks,
+            bytes: self.bytes,
+            loops: self.loops,
+            errors: self.errors,
+            omitted: self.omitted,
+            largest: self.largest.clone(),
+            extensions: self.extension_counts(),
+            deepest: self.deepest.clone(),
+            elapsed_ms: self.started.elapsed().as_millis() as u64,
         }
     }
-}
 
-impl MtimeFilter {
-    fn allows(&self, mtime: Option<SystemTime>) -> bool {
-        match mtime {
-            Some(time) => time >= self.earliest,
-            None => false,
-        }
+    /// `tree` と同じ "3 directories, 12 files" 形式
+    fn footer(&self) -> String {
+        plain_footer(self.directories, self.files)
     }
-}
 
-impl PermFilter {
-    fn allows(&self, perm: Option<u32>) -> bool {
-        match perm {
-            Some(bits) => bits & 0o777 == self.expected,
-            None => false,
+    fn write_plain_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let record = self.record_for_output();
+        writeln!(
+            out,
+            "total size: {} ({} bytes)",
+            format_size_human(record.bytes),
+            record.bytes
+        )?;
+        writeln!(
+            out,
+            "symlinks: {}, loops: {}, errors: {}, omitted: {}",
+            record.symlinks, record.loops, record.errors, record.omitted
+        )?;
+        if let Some(deepest) = &record.deepest {
+            writeln!(out, "deepest: {} (depth {})", deepest.path, deepest.depth)?;
+        }
+        if !record.largest.is_empty() {
+            writeln!(out, "largest:")?;
+            for item in &record.largest {
+                writeln!(out, "  {:>10}  {}", format_size_human(item.size), item.path)?;
+            }
+        }
+        if !record.extensions.is_empty() {
+            writeln!(out, "extensions:")?;
+            for item in &record.extensions {
+                let ext = item.ext.as_deref().unwrap_or("(none)");
+                writeln!(out, "  {:<10} {}", ext, item.count)?;
+            }
         }
+        writeln!(out, "elapsed: {} ms", record.elapsed_ms)
     }
 }
 
-fn parse_size_filter(spec: &str) -> Result<SizeFilter> {
-    let spec = spec.trim();
-    let (cmp, remainder) = if let Some(rest) = spec.strip_prefix(">=") {
-        (SizeCmp::Ge, rest)
-    } else if let Some(rest) = spec.strip_prefix("<=") {
-        (SizeCmp::Le, rest)
-    } else if let Some(rest) = spec.strip_prefix("==") {
-        (SizeCmp::Eq, rest)
-    } else if let Some(rest) = spec.strip_prefix('>') {
-        (SizeCmp::Gt, rest)
-    } else if let Some(rest) = spec.strip_prefix('<') {
-        (SizeCmp::Lt, rest)
+fn plain_footer(directories: u64, files: u64) -> String {
+    let dir_label = if directories == 1 {
+        "directory"
     } else {
-        return Err(anyhow!("invalid --filter-size value: {spec}"));
+        "directories"
     };
+    let file_label = if files == 1 { "file" } else { "files" };
+    format!("\n{directories} {dir_label}, {files} {file_label}\n")
+}
 
-    let remainder = remainder.trim();
-    if remainder.is_empty() {
-        return Err(anyhow!("invalid --filter-size value: {spec}"));
+/// `--stats` の出力を書くか（`--limit-output-bytes` では大きさを見積もれないので出さない）
+fn wants_stats(cli: &Cli) -> bool {
+    cli.stats && cli.limit_output_bytes.is_none()
+}
+
+/// git status を読み込むか（`--sort git-status` も並べ替えのために読み込む）
+fn wants_git_status(cli: &Cli) -> bool {
+    cli.git_status
+        || cli.git_rename
+        || cli.rename_threshold.is_some()
+        || cli.git_submodules
+        || cli.git_changed_only
+        || cli.git_ghosts
+        || cli.git_ignored
+        || matches!(cli.sort, SortMode::GitStatus)
+}
+
+/// LFS のポインタを調べるか（`--type lfs` も中身を読まないと絞り込めない）
+fn wants_lfs(cli: &Cli) -> bool {
+    cli.lfs || cli.types.contains(&TypeFilter::Lfs)
+}
+
+impl GitTracker {
+    fn prepare(root: &Path, cli: &Cli) -> Result<Self> {
+        let lfs = wants_lfs(cli).then(|| LfsStore::open(root));
+        let snapshot = match (cli.rev.as_deref(), cli.source) {
+            (Some(_), Source::Index) => {
+                return Err(anyhow!(
+                    "invalid --source value: index cannot be combined with --rev"
+                ))
+            }
+            (Some(rev), Source::Worktree) => Some(GitSnapshot::open_rev(root, rev, lfs.clone())?),
+            (None, Source::Index) => Some(GitSnapshot::open_index(root, lfs.clone())?),
+            (None, Source::Worktree) => None,
+        };
+        // ステータスはワークツリーとの比較なので、過去のツリーには意味がない。
+        // インデックスはワークツリーと同じパスを持つのでそのまま使う
+        let map = if cli.rev.is_some() {
+            if cli.git_status
+                || cli.git_submodules
+                || cli.git_rename
+                || cli.rename_threshold.is_some()
+                || cli.git_changed_only
+                || cli.git_ghosts
+                || matches!(cli.sort, SortMode::GitStatus)
+            {
+                eprintln!("[warn] git status options ignored with --rev");
+            }
+            None
+        } else {
+            Self::load_status(root, cli)?
+        };
+        Ok(Self {
+            map,
+            log: if cli.git_log {
+                GitLogIndex::build(root, cli.rev.as_deref())?
+            } else {
+                None
+            },
+            churn: None,
+            snapshot,
+            lfs,
+        })
+    }
+
+    /// ルート自身のエントリ。`--rev` / `--source index` では git のツリーから作る
+    fn root_meta(&self, root: &Path) -> EntryMeta {
+        match &self.snapshot {
+            Some(snapshot) => snapshot.root_meta(root),
+            None => {
+                let mut meta = EntryMeta::from_path(root);
+                self.detect_lfs(&mut meta);
+                meta
+            }
+        }
+    }
+
+    /// ファイルシステムのファイルが LFS のポインタなら、実体の大きさと oid に置き換える。
+    /// git のツリーから作ったエントリは [`GitSnapshot`] が済ませている
+    fn detect_lfs(&self, meta: &mut EntryMeta) {
+        let Some(store) = &self.lfs else {
+            return;
+        };
+        let is_small_file = meta.file_type.is_some_and(|ft| ft.is_file())
+            && meta.size.is_some_and(|size| size <= LFS_POINTER_MAX);
+        if !is_small_file {
+            return;
+        }
+        if let Some(pointer) = store.read_pointer(&meta.path) {
+            meta.size = Some(pointer.size);
+            meta.lfs = Some(pointer);
+        }
+    }
+
+    fn load_status(root: &Path, cli: &Cli) -> Result<Option<GitStatusMap>> {
+        // `--sort git-status` も並べ替えのためにステータスを読み込む
+        let renames = cli.git_rename || cli.rename_threshold.is_some();
+        if !wants_git_status(cli) {
+            return Ok(None);
+        }
+        let flag = if cli.git_status || renames {
+            "--git-status"
+        } else if cli.git_submodules {
+            "--git-submodules"
+        } else if cli.git_changed_only {
+            "--git-changed-only"
+        } else if cli.git_ghosts {
+            "--git-ghosts"
+        } else {
+            "--sort git-status"
+        };
+
+        if let Some(threshold) = cli.rename_threshold {
+            if threshold > 100 {
+                return Err(anyhow!(
+                    "invalid --rename-threshold value: {threshold} (expected 0-100)"
+                ));
+            }
+        }
+        if renames {
+            eprintln!("[warn] rename detection enabled (slow)");
+        }
+
+        let cwd = env::current_dir()?;
+        let repo = match Repository::discover(root) {
+            Ok(repo) => repo,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                eprintln!("[warn] {flag} ignored: .git not found");
+                return Ok(None);
+            }
+            Err(err) => return Err(anyhow!(err)),
+        };
+
+        let workdir = match repo.workdir() {
+            Some(dir) => dir.to_path_buf(),
+            None => {
+                eprintln!("[warn] {flag} ignored: repository has no workdir");
+                return Ok(None);
+            }
+        };
+
+        let mut collector = StatusCollector::default();
+        collector.collect(&repo, Path::new(""), cli)?;
+        let StatusCollector {
+            statuses: map,
+            ignored_dirs,
+            renames: rename_map,
+            submodules,
+        } = collector;
+
+        let rollups = git_rollups(&map);
+        let ghosts = if cli.git_ghosts {
+            git_ghosts(&repo, &map)?
+        } else {
+            HashMap::new()
+        };
+        Ok(Some(GitStatusMap {
+            workdir,
+            cwd,
+            statuses: map,
+            ignored_dirs,
+            rollups,
+            renames: rename_map,
+            ghosts,
+            submodules,
+        }))
+    }
+
+    fn apply(&self, meta: &mut EntryMeta) {
+        if let Some(log) = &self.log {
+            meta.last_commit = log.commit_for(&meta.path);
+        }
+        if let Some(churn) = &self.churn {
+            meta.churn = churn.stats_for(&meta.path);
+        }
+        if let Some(map) = &self.map {
+            meta.git_status = map.status_for(&meta.path);
+            meta.rename = map.rename_for(&meta.path);
+            meta.submodule = map.submodule_for(&meta.path);
+            if meta.is_directory() && !meta.is_symlink {
+                meta.git_changes = map.rollup_for(&meta.path);
+            }
+        }
+    }
+
+    /// `--git-ghosts` で `dir` に差し込む削除済みエントリ。同名のパスが実在するものは除く
+    fn ghosts_in(&self, dir: &Path) -> Vec<EntryMeta> {
+        let Some(children) = self.map.as_ref().and_then(|map| map.ghosts_for(dir)) else {
+            return Vec::new();
+        };
+        children
+            .iter()
+            .map(|(name, kind)| (dir.join(name), name, *kind))
+            .filter(|(path, _, _)| fs::symlink_metadata(path).is_err())
+            .map(|(path, name, kind)| {
+                let mut meta = EntryMeta::from_git(path, name.clone(), kind);
+                meta.ghost = true;
+                meta
+            })
+            .collect()
+    }
+
+    /// `dir` 自体が消えていても、削除済みの子を出すために走査を続けるか
+    fn has_ghosts_in(&self, dir: &Path) -> bool {
+        self.map
+            .as_ref()
+            .is_some_and(|map| map.ghosts_for(dir).is_some())
+    }
+
+    /// `--git-changed-only` で残すか。変更のあるエントリと、配下に変更のあるディレクトリだけを残す
+    fn keeps(&self, meta: &EntryMeta, cli: &Cli) -> bool {
+        if !cli.git_changed_only || self.map.is_none() {
+            return true;
+        }
+        meta.git_changes.is_some() || meta.git_status.is_some_and(|s| !s.is_ignored())
+    }
+}
+
+impl GitLogIndex {
+    /// 新しいコミットから順に親（先頭の親）との差分を見て、まだ割り当てていないパスと
+    /// その祖先ディレクトリに割り当てる。HEAD のファイルがすべて埋まったら打ち切る
+    fn build(root: &Path, rev: Option<&str>) -> Result<Option<Self>> {
+        let cwd = env::current_dir()?;
+        let repo = match discover_repository(root) {
+            Ok(repo) => repo,
+            Err(err) if err.code() == ErrorCode::NotFound => {
+                eprintln!("[warn] --git-log ignored: .git not found");
+                return Ok(None);
+            }
+            Err(err) => return Err(anyhow!(err)),
+        };
+        let Some(workdir) = repo.workdir().map(Path::to_path_buf) else {
+            eprintln!("[warn] --git-log ignored: repository has no workdir");
+            return Ok(None);
+        };
+        let start = match rev {
+            Some(rev) => repo
+                .revparse_single(rev)
+                .and_then(|obj| obj.peel_to_commit())
+                .map_err(|err| anyhow!("invalid --rev value: {rev} ({})", err.message()))?,
+            None => match repo.head().and_then(|head| head.peel_to_commit()) {
+                Ok(commit) => commit,
+                Err(_) => {
+                    eprintln!("[warn] --git-log ignored: repository has no commits");
+                    return Ok(None);
+                }
+            },
+        };
+        let head_tree = start.tree()?;
+
+        let mut pending: HashSet<PathBuf> = HashSet::new();
+        head_tree.walk(TreeWalkMode::PreOrder, |dir, entry| {
+            if entry.kind() != Some(git2::ObjectType::Tree) {
+                if let Some(name) = entry.name() {
+                    pending.insert(Path::new(dir).join(name));
+                }
+            }
+            TreeWalkResult::Ok
+        })?;
+
+        let now = Utc::now().timestamp();
+        let mut paths: HashMap<PathBuf, Arc<GitCommitInfo>> = HashMap::new();
+        let mut walk = repo.revwalk()?;
+        // 同じ時刻のコミットでも子が親より先に来るようにする（先に見たコミットが最後に触れたもの）
+        walk.set_sorting(Sort::TOPOLOGICAL | Sort::TIME)?;
+        walk.push(start.id())?;
+        for oid in walk {
+            if pending.is_empty() {
+                break;
+            }
+            let commit = repo.find_commit(oid?)?;
+            // マージコミットは取り込んだ側の変更をまとめて持つので数えない（churn と同じ）。
+            // 変更は取り込まれた側のコミットで割り当てる
+            if commit.parent_count() > 1 {
+                continue;
+            }
+            let tree = commit.tree()?;
+            let parent_tree = match commit.parents().next() {
+                Some(parent) => Some(parent.tree()?),
+                None => None,
+            };
+            let diff = repo.diff_tree_to_tree(parent_tree.as_ref(), Some(&tree), None)?;
+            let mut info: Option<Arc<GitCommitInfo>> = None;
+            for delta in diff.deltas() {
+                let Some(path) = delta.new_file().path().or_else(|| delta.old_file().path()) else {
+                    continue;
+                };
+                if paths.contains_key(path) {
+                    continue;
+                }
+                let info = info
+                    .get_or_insert_with(|| Arc::new(GitCommitInfo::from_commit(&commit, now)))
+                    .clone();
+                pending.remove(path);
+                for ancestor in path.ancestors() {
+                    if paths.contains_key(ancestor) {
+                        break;
+                    }
+                    paths.insert(ancestor.to_path_buf(), info.clone());
+                }
+            }
+        }
+
+        Ok(Some(Self {
+            workdir,
+            cwd,
+            paths,
+        }))
+    }
+
+    fn commit_for(&self, path: &Path) -> Option<Arc<GitCommitInfo>> {
+        let rel = workdir_relative(&self.workdir, &self.cwd, path)?;
+        self.paths.get(&rel).cloned()
+    }
+}
+
+impl GitSnapshot {
+    fn open_rev(root: &Path, rev: &str, lfs: Option<LfsStore>) -> Result<Self> {
+        let (repo, workdir, cwd) = Self::open_repository(root, "--rev")?;
+        let tree = repo
+            .revparse_single(rev)
+            .and_then(|obj| obj.peel_to_tree())
+            .map_err(|err| anyhow!("invalid --rev value: {rev} ({})", err.message()))?
+            .id();
+        let snapshot = Self {
+            repo,
+            source: SnapshotSource::Tree(tree),
+            lfs,
+            workdir,
+            cwd,
+        };
+        if !snapshot.contains(root) {
+            return Err(anyhow!("{} not found in {rev}", root.display()));
+        }
+        Ok(snapshot)
+    }
+
+    fn open_index(root: &Path, lfs: Option<LfsStore>) -> Result<Self> {
+        let (repo, workdir, cwd) = Self::open_repository(root, "--source index")?;
+        let listing = IndexListing::read(&repo)?;
+        let snapshot = Self {
+            repo,
+            source: SnapshotSource::Index(listing),
+            lfs,
+            workdir,
+            cwd,
+        };
+        if !snapshot.contains(root) {
+            return Err(anyhow!("{} not found in the index", root.display()));
+        }
+        Ok(snapshot)
+    }
+
+    fn open_repository(root: &Path, flag: &str) -> Result<(Repository, PathBuf, PathBuf)> {
+        let cwd = env::current_dir()?;
+        let repo = discover_repository(root)
+            .with_context(|| format!("{flag} requires a git repository"))?;
+        let workdir = repo
+            .workdir()
+            .map(Path::to_path_buf)
+            .with_context(|| format!("{flag} requires a repository with a workdir"))?;
+        Ok((repo, workdir, cwd))
+    }
+
+    fn relative(&self, path: &Path) -> Option<PathBuf> {
+        workdir_relative(&self.workdir, &self.cwd, path)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.relative(path)
+            .is_some_and(|rel| rel.as_os_str().is_empty() || self.lookup(&rel).is_some())
+    }
+
+    /// ワークツリー相対のパスのモードとオブジェクト。衝突中のパスは最初のステージを返す
+    fn lookup(&self, rel: &Path) -> Option<(i32, Oid, Option<u8>)> {
+        match &self.source {
+            SnapshotSource::Tree(tree) => {
+                let tree = self.repo.find_tree(*tree).ok()?;
+                let entry = tree.get_path(rel).ok()?;
+                Some((entry.filemode(), entry.id(), None))
+            }
+            SnapshotSource::Index(listing) => listing
+                .item(rel)
+                .map(|item| (item.mode, item.id, item.stage)),
+        }
+    }
+
+    /// `path` のディレクトリのツリー。コミットに無い・ディレクトリでなければ None
+    fn subtree(&self, tree: Oid, path: &Path) -> Option<git2::Tree<'_>> {
+        let rel = self.relative(path)?;
+        if rel.as_os_str().is_empty() {
+            return self.repo.find_tree(tree).ok();
+        }
+        let (mode, id, _) = self.lookup(&rel)?;
+        if mode & GIT_MODE_TYPE_MASK != GIT_MODE_TREE {
+            return None;
+        }
+        self.repo.find_tree(id).ok()
+    }
+
+    fn root_meta(&self, root: &Path) -> EntryMeta {
+        let name = root
+            .file_name()
+            .map(OsString::from)
+            .unwrap_or_else(|| root.as_os_str().to_owned());
+        match self
+            .relative(root)
+            .filter(|rel| !rel.as_os_str().is_empty())
+        {
+            Some(rel) => match self.lookup(&rel) {
+                Some((mode, id, stage)) => {
+                    self.entry_meta(root.to_path_buf(), name, mode, id, stage)
+                }
+                None => EntryMeta::from_git(root.to_path_buf(), name, EntryKind::Unknown),
+            },
+            None => {
+                let mut meta = EntryMeta::from_git(root.to_path_buf(), name, EntryKind::Dir);
+                meta.perm_unix = Some(GIT_MODE_TREE as u32);
+                meta
+            }
+        }
+    }
+
+    fn children(&self, dir: &Path) -> Option<Vec<EntryMeta>> {
+        match &self.source {
+            SnapshotSource::Tree(tree) => {
+                let tree = self.subtree(*tree, dir)?;
+                let children = tree
+                    .iter()
+                    .map(|entry| {
+                        let name = match entry.name() {
+                            Some(name) => OsString::from(name),
+                            None => OsString::from(
+                                String::from_utf8_lossy(entry.name_bytes()).into_owned(),
+                            ),
+                        };
+                        self.entry_meta(dir.join(&name), name, entry.filemode(), entry.id(), None)
+                    })
+                    .collect();
+                Some(children)
+            }
+            SnapshotSource::Index(listing) => {
+                let items = listing.dirs.get(&self.relative(dir)?)?;
+                let children = items
+                    .iter()
+                    .map(|((name, _), item)| {
+                        self.entry_meta(
+                            dir.join(name),
+                            name.clone(),
+                            item.mode,
+                            item.id,
+                            item.stage,
+                        )
+                    })
+                    .collect();
+                Some(children)
+            }
+        }
+    }
+
+    /// git のモードから、モード・blob のサイズ・リンク先を持つエントリを作る。
+    /// サブモジュールは中身を辿らないディレクトリとして扱う
+    fn entry_meta(
+        &self,
+        path: PathBuf,
+        name: OsString,
+        mode: i32,
+        id: Oid,
+        stage: Option<u8>,
+    ) -> EntryMeta {
+        let kind = match mode & GIT_MODE_TYPE_MASK {
+            GIT_MODE_TREE | GIT_MODE_SUBMODULE => EntryKind::Dir,
+            GIT_MODE_SYMLINK => EntryKind::Symlink,
+            _ => EntryKind::File,
+        };
+        let mut meta = EntryMeta::from_git(path, name, kind);
+        meta.perm_unix = Some(mode as u32);
+        meta.stage = stage;
+        match kind {
+            EntryKind::File => {
+                meta.size = self.blob_size(id);
+                if let Some(store) = &self.lfs {
+                    if meta.size.is_some_and(|size| size <= LFS_POINTER_MAX) {
+                        let pointer = self
+                            .repo
+                            .find_blob(id)
+                            .ok()
+                            .and_then(|blob| store.pointer(blob.content()));
+                        if let Some(pointer) = pointer {
+                            meta.size = Some(pointer.size);
+                            meta.lfs = Some(pointer);
+                        }
+                    }
+                }
+            }
+            EntryKind::Symlink => {
+                meta.is_symlink = true;
+                meta.symlink_target = self.repo.find_blob(id).ok().map(|blob| {
+                    PathBuf::from(String::from_utf8_lossy(blob.content()).into_owned())
+                });
+            }
+            _ => {}
+        }
+        meta
+    }
+
+    /// 中身を展開せずにヘッダーだけ読む
+    fn blob_size(&self, id: Oid) -> Option<u64> {
+        let odb = self.repo.odb().ok()?;
+        odb.read_header(id).ok().map(|(size, _)| size as u64)
+    }
+}
+
+impl IndexListing {
+    /// インデックスの全エントリを親ディレクトリごとに振り分け、途中のディレクトリも補う
+    fn read(repo: &Repository) -> Result<Self> {
+        let index = repo.index()?;
+        let mut dirs: HashMap<PathBuf, BTreeMap<(OsString, u8), IndexItem>> = HashMap::new();
+        for entry in index.iter() {
+            let path = PathBuf::from(String::from_utf8_lossy(&entry.path).into_owned());
+            let stage = ((entry.flags & GIT_INDEX_STAGE_MASK) >> GIT_INDEX_STAGE_SHIFT) as u8;
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+            dirs.entry(parent.to_path_buf()).or_default().insert(
+                (name.to_owned(), stage),
+                IndexItem {
+                    mode: entry.mode as i32,
+                    id: entry.id,
+                    stage: Some(stage),
+                },
+            );
+            for dir in parent.ancestors() {
+                let (Some(up), Some(name)) = (dir.parent(), dir.file_name()) else {
+                    break;
+                };
+                dirs.entry(up.to_path_buf())
+                    .or_default()
+                    .entry((name.to_owned(), 0))
+                    .or_insert(IndexItem {
+                        mode: GIT_MODE_TREE,
+                        id: Oid::zero(),
+                        stage: None,
+                    });
+            }
+        }
+        Ok(Self { dirs })
+    }
+
+    fn item(&self, rel: &Path) -> Option<&IndexItem> {
+        let (parent, name) = (rel.parent()?, rel.file_name()?);
+        self.dirs
+            .get(parent)?
+            .range((name.to_owned(), 0)..=(name.to_owned(), u8::MAX))
+            .next()
+            .map(|(_, item)| item)
+    }
+}
+
+impl LfsStore {
+    fn open(root: &Path) -> Self {
+        let objects = match discover_repository(root) {
+            Ok(repo) => {
+                // 追加のワークツリーでは実体は共通の .git 側にある
+                let git_dir = repo.path().to_path_buf();
+                let common = fs::read_to_string(git_dir.join("commondir"))
+                    .map(|dir| git_dir.join(dir.trim()))
+                    .unwrap_or(git_dir);
+                Some(common.join("lfs").join("objects"))
+            }
+            Err(_) => {
+                eprintln!("[warn] --lfs: .git not found, objects are reported as not downloaded");
+                None
+            }
+        };
+        Self { objects }
+    }
+
+    fn read_pointer(&self, path: &Path) -> Option<LfsPointer> {
+        let mut content = Vec::new();
+        fs::File::open(path)
+            .ok()?
+            .take(LFS_POINTER_MAX + 1)
+            .read_to_end(&mut content)
+            .ok()?;
+        self.pointer(&content)
+    }
+
+    /// 先頭の `version` 行で判定し、`oid sha256:...` と `size ...` を読む
+    fn pointer(&self, content: &[u8]) -> Option<LfsPointer> {
+        if content.len() as u64 > LFS_POINTER_MAX || !content.starts_with(LFS_VERSION_PREFIX) {
+            return None;
+        }
+        let text = std::str::from_utf8(content).ok()?;
+        let (mut oid, mut size) = (None, None);
+        for line in text.lines().skip(1) {
+            if let Some(hash) = line.strip_prefix("oid sha256:") {
+                if hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    oid = Some(hash.to_ascii_lowercase());
+                }
+            } else if let Some(value) = line.strip_prefix("size ") {
+                size = value.parse().ok();
+            }
+        }
+        let (oid, size) = (oid?, size?);
+        let local = self
+            .objects
+            .as_ref()
+            .is_some_and(|dir| dir.join(&oid[..2]).join(&oid[2..4]).join(&oid).is_file());
+        Some(LfsPointer { oid, size, local })
+    }
+}
+
+/// `root` が今のワークツリーに無くても（`--rev` で過去のパスを指す場合など）、実在する祖先から探す
+pub(crate) fn discover_repository(root: &Path) -> std::result::Result<Repository, git2::Error> {
+    let start = root
+        .ancestors()
+        .find(|dir| !dir.as_os_str().is_empty() && dir.exists())
+        .unwrap_or(Path::new("."));
+    Repository::discover(start)
+}
+
+impl GitCommitInfo {
+    fn from_commit(commit: &Commit<'_>, now: i64) -> Self {
+        let hash = commit.id().to_string();
+        let time = commit.time().seconds();
+        let date = DateTime::from_timestamp(time, 0)
+            .map(|utc| utc.with_timezone(&Local).to_rfc3339())
+            .unwrap_or_default();
+        Self {
+            hash: hash[..hash.len().min(7)].to_string(),
+            author: commit.author().name().unwrap_or_default().to_string(),
+            date,
+            relative: format_relative_age(now - time),
+            subject: commit.summary().unwrap_or_default().to_string(),
+        }
+    }
+}
+
+impl std::fmt::Display for GitCommitInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {}, {}: {}",
+            self.hash, self.author, self.relative, self.subject
+        )
+    }
+}
+
+/// `3 days ago` のような経過時間の表記
+fn format_relative_age(secs: i64) -> String {
+    const MINUTE: i64 = 60;
+    const HOUR: i64 = 60 * MINUTE;
+    const DAY: i64 = 24 * HOUR;
+    let secs = secs.max(0);
+    let (count, unit) = if secs < MINUTE {
+        (secs, "second")
+    } else if secs < HOUR {
+        (secs / MINUTE, "minute")
+    } else if secs < DAY {
+        (secs / HOUR, "hour")
+    } else if secs < 30 * DAY {
+        (secs / DAY, "day")
+    } else if secs < 365 * DAY {
+        (secs / (30 * DAY), "month")
+    } else {
+        (secs / (365 * DAY), "year")
+    };
+    if count == 1 {
+        format!("1 {unit} ago")
+    } else {
+        format!("{count} {unit}s ago")
+    }
+}
+
+/// ワークツリーからの相対パス。相対パスは実行時のカレントディレクトリを基準にする
+pub(crate) fn workdir_relative(workdir: &Path, cwd: &Path, path: &Path) -> Option<PathBuf> {
+    let abs = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        cwd.join(path)
+    };
+    abs.strip_prefix(workdir).ok().map(Path::to_path_buf)
+}
+
+impl GitStatusMap {
+    fn workdir_relative(&self, path: &Path) -> Option<PathBuf> {
+        workdir_relative(&self.workdir, &self.cwd, path)
+    }
+
+    fn rename_for(&self, path: &Path) -> Option<GitRename> {
+        if self.renames.is_empty() {
+            return None;
+        }
+        let rel = self.workdir_relative(path)?;
+        self.renames.get(&rel).cloned()
+    }
+
+    fn ghosts_for(&self, dir: &Path) -> Option<&BTreeMap<OsString, EntryKind>> {
+        if self.ghosts.is_empty() {
+            return None;
+        }
+        let rel = self.workdir_relative(dir)?;
+        self.ghosts.get(&rel)
+    }
+
+    fn submodule_for(&self, path: &Path) -> Option<GitSubmodule> {
+        if self.submodules.is_empty() {
+            return None;
+        }
+        let rel = self.workdir_relative(path)?;
+        self.submodules.get(&rel).copied()
+    }
+
+    fn rollup_for(&self, path: &Path) -> Option<GitRollup> {
+        let rel = self.workdir_relative(path)?;
+        self.rollups.get(&rel).copied()
+    }
+
+    fn status_for(&self, path: &Path) -> Option<GitStatus> {
+        let rel = self.workdir_relative(path)?;
+        let rel = rel.as_path();
+        if rel.as_os_str().is_empty() {
+            return None;
+        }
+        if let Some(status) = self.statuses.get(rel) {
+            return Some(*status);
+        }
+        if self.ignored_dirs.is_empty() {
+            return None;
+        }
+        rel.ancestors()
+            .skip(1)
+            .any(|dir| self.ignored_dirs.contains(dir))
+            .then_some(GitStatus::IGNORED)
+    }
+}
+
+fn status_entry_path(entry: &git2::StatusEntry<'_>) -> Option<PathBuf> {
+    if let Some(delta) = entry.index_to_workdir() {
+        if let Some(path) = delta.new_file().path() {
+            return Some(path.to_path_buf());
+        }
+    }
+    if let Some(delta) = entry.head_to_index() {
+        if let Some(path) = delta.new_file().path() {
+            return Some(path.to_path_buf());
+        }
+        if let Some(path) = delta.old_file().path() {
+            return Some(path.to_path_buf());
+        }
+    }
+    entry.path().map(PathBuf::from)
+}
+
+impl GitStatus {
+    const IGNORED: GitStatus = GitStatus {
+        index: '!',
+        worktree: '!',
+    };
+
+    /// libgit2 のフラグから XY を組み立てる。変更がなければ None
+    fn from_git(status: Status) -> Option<Self> {
+        if status.is_conflicted() {
+            return Some(Self {
+                index: 'U',
+                worktree: 'U',
+            });
+        }
+        if status.is_ignored() {
+            return Some(Self::IGNORED);
+        }
+        if status.is_wt_new() && !status.intersects(Status::INDEX_NEW) {
+            return Some(Self {
+                index: '?',
+                worktree: '?',
+            });
+        }
+
+        let index = if status.is_index_new() {
+            'A'
+        } else if status.is_index_deleted() {
+            'D'
+        } else if status.is_index_renamed() {
+            'R'
+        } else if status.is_index_typechange() {
+            'T'
+        } else if status.is_index_modified() {
+            'M'
+        } else {
+            ' '
+        };
+        let worktree = if status.is_wt_deleted() {
+            'D'
+        } else if status.is_wt_renamed() {
+            'R'
+        } else if status.is_wt_typechange() {
+            'T'
+        } else if status.is_wt_modified() {
+            'M'
+        } else {
+            ' '
+        };
+        (index != ' ' || worktree != ' ').then_some(Self { index, worktree })
+    }
+
+    fn is_ignored(self) -> bool {
+        self == Self::IGNORED
+    }
+
+    /// 同じパスに複数のエントリ（リネームの両側など）があれば桁ごとに強い方を残す
+    fn merge(self, other: GitStatus) -> GitStatus {
+        let pick = |a: char, b: char| {
+            if git_status_priority(b) > git_status_priority(a) {
+                b
+            } else {
+                a
+            }
+        };
+        GitStatus {
+            index: pick(self.index, other.index),
+            worktree: pick(self.worktree, other.worktree),
+        }
+    }
+
+    /// 集計で数える記号（強い方の桁）
+    fn rollup_symbol(self) -> char {
+        if git_status_priority(self.worktree) > git_status_priority(self.index) {
+            self.worktree
+        } else {
+            self.index
+        }
+    }
+
+    /// `--sort git-status` 用の重み（強い方の桁で決める）
+    fn priority(self) -> u8 {
+        git_status_priority(self.index).max(git_status_priority(self.worktree))
+    }
+}
+
+impl std::fmt::Display for GitStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}{}", self.index, self.worktree)
+    }
+}
+
+impl GitRollup {
+    fn add(&mut self, symbol: char) {
+        if let Some(idx) = GIT_ROLLUP_ORDER.iter().position(|c| *c == symbol) {
+            self.counts[idx] += 1;
+        }
+    }
+
+    fn entries(&self) -> impl Iterator<Item = (char, u32)> + '_ {
+        GIT_ROLLUP_ORDER
+            .iter()
+            .zip(self.counts)
+            .filter(|(_, count)| *count > 0)
+            .map(|(symbol, count)| (*symbol, count))
+    }
+
+    fn priority(&self) -> u8 {
+        self.entries()
+            .map(|(symbol, _)| git_status_priority(symbol))
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+impl std::fmt::Display for GitRollup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (idx, (symbol, count)) in self.entries().enumerate() {
+            if idx > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{symbol}{count}")?;
+        }
+        Ok(())
+    }
+}
+
+/// JSON では `{"M": 3, "A": 1}`
+impl Serialize for GitRollup {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeMap;
+        let mut map = serializer.serialize_map(None)?;
+        for (symbol, count) in self.entries() {
+            map.serialize_entry(&symbol, &count)?;
+        }
+        map.end()
+    }
+}
+
+impl StatusCollector {
+    /// `repo` のステータスを `prefix`（外側のワークツリーから見た `repo` の位置）の下に加える
+    fn collect(&mut self, repo: &Repository, prefix: &Path, cli: &Cli) -> Result<()> {
+        let Some(workdir) = repo.workdir().map(Path::to_path_buf) else {
+            return Ok(());
+        };
+        let renames = cli.git_rename || cli.rename_threshold.is_some();
+        let mut opts = StatusOptions::new();
+        opts.include_untracked(true)
+            .recurse_untracked_dirs(true)
+            // `git status --ignored` と同じく、無視されたパスは頼まれたときだけ調べる
+            .include_ignored(cli.git_ignored)
+            .recurse_ignored_dirs(false)
+            .include_unreadable(true);
+        if renames {
+            opts.renames_head_to_index(true);
+            opts.renames_index_to_workdir(true);
+            opts.renames_from_rewrites(true);
+            if let Some(threshold) = cli.rename_threshold {
+                opts.rename_threshold(threshold);
+            }
+        }
+
+        let statuses = repo.statuses(Some(&mut opts))?;
+        // libgit2 は入れ子のリポジトリを未追跡のディレクトリ 1 件として返す
+        let mut nested = Vec::new();
+        for entry in statuses.iter() {
+            // ステージ済みのリネームを優先し、なければワークツリー側
+            let delta = entry
+                .head_to_index()
+                .filter(|d| d.status() == git2::Delta::Renamed)
+                .or_else(|| {
+                    entry
+                        .index_to_workdir()
+                        .filter(|d| d.status() == git2::Delta::Renamed)
+                });
+            if let Some(delta) = delta {
+                if let (Some(from), Some(to)) = (delta.old_file().path(), delta.new_file().path()) {
+                    let rename = GitRename {
+                        from: prefix.join(from),
+                        similarity: rename_similarity(repo, &workdir, &delta),
+                    };
+                    self.renames.insert(prefix.join(to), rename);
+                }
+            }
+            if let Some(status) = GitStatus::from_git(entry.status()) {
+                if let Some(path) = status_entry_path(&entry) {
+                    let is_dir = entry.path_bytes().ends_with(b"/");
+                    if is_dir
+                        && cli.git_submodules
+                        && entry.status().contains(Status::WT_NEW)
+                        && workdir.join(&path).join(".git").exists()
+                    {
+                        nested.push(path.clone());
+                    }
+                    if status.is_ignored() && is_dir {
+                        self.ignored_dirs.insert(prefix.join(&path));
+                    }
+                    update_git_status(&mut self.statuses, prefix.join(path), status);
+                }
+            }
+        }
+
+        for submodule in repo.submodules()? {
+            let path = submodule.path().to_path_buf();
+            let Some(name) = submodule.name() else {
+                continue;
+            };
+            let state = match repo.submodule_status(name, SubmoduleIgnore::None) {
+                Ok(status) => GitSubmodule::from_git(status),
+                Err(err) => {
+                    eprintln!("[warn] submodule {}: {}", path.display(), err.message());
+                    continue;
+                }
+            };
+            if cli.git_submodules && !state.uninitialized {
+                match submodule.open() {
+                    Ok(sub) => self.collect(&sub, &prefix.join(&path), cli)?,
+                    Err(err) => {
+                        eprintln!("[warn] submodule {}: {}", path.display(), err.message())
+                    }
+                }
+            }
+            self.submodules.insert(prefix.join(path), state);
+        }
+
+        for path in nested {
+            match Repository::open(workdir.join(&path)) {
+                Ok(inner) => self.collect(&inner, &prefix.join(&path), cli)?,
+                Err(err) => eprintln!("[warn] {}: {}", path.display(), err.message()),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl GitSubmodule {
+    fn from_git(status: SubmoduleStatus) -> Self {
+        Self {
+            uninitialized: status.contains(SubmoduleStatus::WD_UNINITIALIZED),
+            new_commits: status.contains(SubmoduleStatus::WD_MODIFIED),
+            dirty: status.intersects(
+                SubmoduleStatus::WD_INDEX_MODIFIED
+                    | SubmoduleStatus::WD_WD_MODIFIED
+                    | SubmoduleStatus::WD_UNTRACKED,
+            ),
+        }
+    }
+
+    fn is_clean(&self) -> bool {
+        !(self.uninitialized || self.new_commits || self.dirty)
+    }
+}
+
+impl std::fmt::Display for GitSubmodule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.uninitialized {
+            return f.write_str("uninitialized");
+        }
+        match (self.new_commits, self.dirty) {
+            (true, true) => f.write_str("new commits, dirty"),
+            (true, false) => f.write_str("new commits"),
+            (false, true) => f.write_str("dirty"),
+            (false, false) => f.write_str("clean"),
+        }
+    }
+}
+
+/// リネーム前後の内容を行単位で比べた一致率。libgit2 の類似度は git2 から読めないので、
+/// 全行をコンテキストにしたパッチから「変わらなかった行 / 多い方の行数」で求める
+fn rename_similarity(repo: &Repository, workdir: &Path, delta: &DiffDelta<'_>) -> Option<u8> {
+    let old = repo.find_blob(delta.old_file().id()).ok()?;
+    let new_id = delta.new_file().id();
+    let new_content = if new_id.is_zero() {
+        fs::read(workdir.join(delta.new_file().path()?)).ok()?
+    } else {
+        repo.find_blob(new_id).ok()?.content().to_vec()
+    };
+    if old.content() == new_content.as_slice() {
+        return Some(100);
+    }
+
+    let mut opts = DiffOptions::new();
+    opts.context_lines(u32::MAX);
+    let patch =
+        Patch::from_buffers(old.content(), None, &new_content, None, Some(&mut opts)).ok()?;
+    if patch.delta().flags().is_binary() {
+        return None;
+    }
+    let (context, additions, deletions) = patch.line_stats().ok()?;
+    let total = (context + additions).max(context + deletions);
+    if total == 0 {
+        return Some(100);
+    }
+    Some((context * 100 / total) as u8)
+}
+
+/// 削除済みのパスを親ディレクトリごとにまとめる。丸ごと消えたディレクトリも
+/// ディレクトリとして親に載せ、種類はインデックス（なければ HEAD）のモードから決める
+fn git_ghosts(
+    repo: &Repository,
+    statuses: &HashMap<PathBuf, GitStatus>,
+) -> Result<HashMap<PathBuf, BTreeMap<OsString, EntryKind>>> {
+    let index = repo.index()?;
+    let head = repo.head().ok().and_then(|head| head.peel_to_tree().ok());
+    let mut ghosts: HashMap<PathBuf, BTreeMap<OsString, EntryKind>> = HashMap::new();
+    for (path, status) in statuses {
+        if status.index != 'D' && status.worktree != 'D' {
+            continue;
+        }
+        let mode = index
+            .get_path(path, 0)
+            .map(|entry| entry.mode as i32)
+            .or_else(|| {
+                head.as_ref()
+                    .and_then(|tree| tree.get_path(path).ok())
+                    .map(|entry| entry.filemode())
+            });
+        let kind = match mode {
+            Some(GIT_MODE_SYMLINK) => EntryKind::Symlink,
+            // サブモジュール（gitlink）は中身を持たないディレクトリとして出す
+            Some(GIT_MODE_SUBMODULE) => EntryKind::Dir,
+            _ => EntryKind::File,
+        };
+
+        let mut child = path.as_path();
+        let mut child_kind = kind;
+        while let (Some(parent), Some(name)) = (child.parent(), child.file_name()) {
+            ghosts
+                .entry(parent.to_path_buf())
+                .or_default()
+                .entry(name.to_os_string())
+                .or_insert(child_kind);
+            child = parent;
+            child_kind = EntryKind::Dir;
+        }
+    }
+    Ok(ghosts)
+}
+
+/// 変更のあったパスを祖先ディレクトリすべてに数え上げる（無視されたものは数えない）
+fn git_rollups(statuses: &HashMap<PathBuf, GitStatus>) -> HashMap<PathBuf, GitRollup> {
+    let mut rollups: HashMap<PathBuf, GitRollup> = HashMap::new();
+    for (path, status) in statuses {
+        if status.is_ignored() {
+            continue;
+        }
+        let symbol = status.rollup_symbol();
+        for dir in path.ancestors().skip(1) {
+            rollups.entry(dir.to_path_buf()).or_default().add(symbol);
+        }
+    }
+    rollups
+}
+
+fn update_git_status(map: &mut HashMap<PathBuf, GitStatus>, path: PathBuf, status: GitStatus) {
+    map.entry(path)
+        .and_modify(|current| *current = current.merge(status))
+        .or_insert(status);
+}
+
+fn git_status_priority(symbol: char) -> u8 {
+    match symbol {
+        'U' => 7,
+        'D' => 6,
+        'R' => 5,
+        'A' => 4,
+        'M' | 'T' => 3,
+        '?' => 2,
+        '!' => 1,
+        _ => 0,
+    }
+}
+
+struct Filters {
+    size: Option<SizeFilter>,
+    dir_size: Option<SizeFilter>,
+    /// `--filter-dir-size` と `--sort size` が共有するディレクトリの再帰合計
+    dir_sizes: Option<DirSizeIndex>,
+    mtime: Option<TimeFilter>,
+    ctime: Option<TimeFilter>,
+    atime: Option<TimeFilter>,
+    perm: Vec<PermFilter>,
+    prune: bool,
+    /// `--prune` と権限条件で使う先読みの結果
+    lookahead: LookaheadCache,
+    normalization: Option<NormalizationCheck>,
+    summary: Option<SummaryIndex>,
+    /// `--type lfs` だけでファイルを絞るとき、ポインタ以外のファイルを落とす
+    lfs_only: bool,
+}
+
+/// `--summary-depth` 用に、表示と同じフィルタで全体を走査して求めたディレクトリごとの集計
+struct SummaryIndex {
+    totals: HashMap<PathBuf, DirTotals>,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct DirTotals {
+    files: u64,
+    dirs: u64,
+    size: u64,
+}
+
+/// 先読みで表示されると分かったディレクトリの、絞り込み済みのエントリ。
+/// 走査側が実際に降りたときに 1 度だけ取り出し、ディレクトリを読み直さずに使う。
+#[derive(Default)]
+struct LookaheadCache {
+    frames: RefCell<HashMap<PathBuf, Vec<EntryMeta>>>,
+}
+
+/// `--check-normalization` の報告済みディレクトリ。`--summary-depth` の集計で同じ
+/// ディレクトリを 2 度読んでも警告は 1 回にする。
+#[derive(Default)]
+struct NormalizationCheck {
+    reported: RefCell<HashSet<PathBuf>>,
+}
+
+#[derive(Clone, Copy)]
+enum CmpOp {
+    Lt,
+    Le,
+    Eq,
+    Ge,
+    Gt,
+}
+
+struct SizeFilter {
+    clauses: Vec<SizeClause>,
+}
+
+struct SizeClause {
+    cmp: CmpOp,
+    threshold: u64,
+}
+
+/// `--filter-dir-size` / `--sort size` 用に事前走査で求めたディレクトリごとの再帰合計サイズ
+struct DirSizeIndex {
+    totals: HashMap<PathBuf, u64>,
+}
+
+/// 時刻フィルタ。下限・上限のどちらか（または両方）を持つ
+#[derive(Debug, PartialEq)]
+struct TimeFilter {
+    lower: Option<TimeBound>,
+    upper: Option<TimeBound>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct TimeBound {
+    at: SystemTime,
+    inclusive: bool,
+}
+
+/// 時刻指定 1 つ分を解決した区間 `[start, end)`。
+/// 相対指定や日時指定では `start == end` の瞬間になる。
+#[derive(Clone, Copy, Debug)]
+struct TimeSpan {
+    start: SystemTime,
+    end: SystemTime,
+}
+
+#[derive(Debug, PartialEq)]
+struct PermFilter {
+    mode: PermMatch,
+    bits: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PermMatch {
+    /// `mode & 0o7777 == bits`
+    Exact,
+    /// 指定ビットをすべて含む
+    All,
+    /// 指定ビットのいずれかを含む
+    Any,
+}
+
+impl Filters {
+    /// フィルタ以外の事前集計（`--summary-depth`）をエントリに付ける
+    fn annotate(&self, meta: &mut EntryMeta) {
+        if let Some(summary) = &self.summary {
+            if meta.is_directory() && !meta.is_symlink {
+                meta.totals = Some(summary.totals.get(&meta.path).copied().unwrap_or_default());
+            }
+        }
+    }
+
+    /// `--rev` / `--source index` ではディレクトリの合計も git のツリーから、
+    /// `--lfs` では LFS の実体の大きさで求める
+    fn use_git(&mut self, cli: &Cli, root: &Path, git: &GitTracker) {
+        if self.dir_size.is_none() && !matches!(cli.sort, SortMode::Size) {
+            return;
+        }
+        if let Some(snapshot) = &git.snapshot {
+            self.dir_sizes = Some(DirSizeIndex::from_snapshot(snapshot, root, cli.hidden));
+        } else if git.lfs.is_some() {
+            self.dir_sizes = Some(DirSizeIndex::build(root, cli.hidden, git.lfs.as_ref()));
+        }
+    }
+
+    fn from_cli(cli: &Cli, root: &Path) -> Result<Self> {
+        let size = if let Some(spec) = cli.filter_size.as_deref() {
+            Some(parse_size_filter(spec, "--filter-size")?)
+        } else {
+            None
+        };
+
+        let dir_size = if let Some(spec) = cli.filter_dir_size.as_deref() {
+            Some(parse_size_filter(spec, "--filter-dir-size")?)
+        } else {
+            None
+        };
+        // `--rev` / `--source index` / `--lfs` のときは use_git で作る
+        let dir_sizes = (cli.rev.is_none()
+            && cli.source == Source::Worktree
+            && !wants_lfs(cli)
+            && (dir_size.is_some() || matches!(cli.sort, SortMode::Size)))
+        .then(|| DirSizeIndex::build(root, cli.hidden, None));
+
+        let now = SystemTime::now();
+        let mtime = if let Some(spec) = cli.filter_mtime.as_deref() {
+            Some(parse_time_filter(spec, "--filter-mtime", now)?)
+        } else {
+            None
+        };
+
+        let ctime = if let Some(spec) = cli.filter_ctime.as_deref() {
+            Some(parse_time_filter(spec, "--filter-ctime", now)?)
+        } else {
+            None
+        };
+
+        let atime = if let Some(spec) = cli.filter_atime.as_deref() {
+            Some(parse_time_filter(spec, "--filter-atime", now)?)
+        } else {
+            None
+        };
+
+        let mut perm = Vec::new();
+        if let Some(spec) = cli.filter_perm.as_deref() {
+            perm.extend(parse_perm_filter(spec)?);
+        }
+        perm.extend(convenience_perm_filters(cli));
+
+        Ok(Self {
+            size,
+            dir_size,
+            dir_sizes,
+            mtime,
+            ctime,
+            atime,
+            perm,
+            prune: cli.prune,
+            lookahead: LookaheadCache::default(),
+            normalization: cli.check_normalization.then(NormalizationCheck::default),
+            summary: None,
+            lfs_only: cli.types.contains(&TypeFilter::Lfs)
+                && !cli.types.contains(&TypeFilter::File),
+        })
+    }
+
+    fn allows(&self, meta: &EntryMeta) -> bool {
+        let is_dir = meta.points_to_directory();
+        if self.lfs_only && !is_dir && !meta.is_symlink && meta.lfs.is_none() {
+            return false;
+        }
+        if let Some(size) = &self.size {
+            // ディレクトリ自体の st_size は意味を持たないので --filter-dir-size に任せる
+            if !is_dir && !size.allows(meta.size) {
+                return false;
+            }
+        }
+
+        // 合計は辿った先の実体を数えないので、ディレクトリへのシンボリックリンクは葉として素通しにする
+        if let (Some(filter), Some(index)) = (&self.dir_size, &self.dir_sizes) {
+            if is_dir && !meta.is_symlink && !filter.allows(index.total_for(&meta.path)) {
+                return false;
+            }
+        }
+
+        // 時刻条件もファイルのみに適用する。ディレクトリの時刻は中のファイルを足し引きするたびに
+        // 新しくなるので、古いファイルを探すときに親ごと落とさないようにする
+        if !is_dir {
+            if let Some(mtime) = &self.mtime {
+                if !mtime.allows(meta.mtime) {
+                    return false;
+                }
+            }
+
+            if let Some(ctime) = &self.ctime {
+                if !ctime.allows(meta.ctime) {
+                    return false;
+                }
+            }
+
+            if let Some(atime) = &self.atime {
+                if !atime.allows(meta.atime) {
+                    return false;
+                }
+            }
+        }
+
+        // 条件を満たさないディレクトリも、一致する子孫を探すためここでは落とさない。
+        // 子孫に一致がなければ read_dir_frame で除く。辿らないシンボリックリンクはその場で判定する
+        if (!is_dir || meta.is_symlink) && !self.perm_allows(meta) {
+            return false;
+        }
+
+        true
+    }
+
+    fn perm_allows(&self, meta: &EntryMeta) -> bool {
+        self.perm.iter().all(|perm| perm.allows(meta.perm_unix))
+    }
+}
+
+impl LookaheadCache {
+    fn take(&self, path: &Path) -> Option<Vec<EntryMeta>> {
+        self.frames.borrow_mut().remove(path)
+    }
+
+    fn contains(&self, path: &Path) -> bool {
+        self.frames.borrow().contains_key(path)
+    }
+
+    fn record(&self, path: PathBuf, entries: Vec<EntryMeta>) {
+        self.frames.borrow_mut().insert(path, entries);
+    }
+}
+
+impl SizeFilter {
+    fn allows(&self, size: Option<u64>) -> bool {
+        let Some(size) = size else {
+            return false;
+        };
+        self.clauses.iter().all(|clause| match clause.cmp {
+            CmpOp::Lt => size < clause.threshold,
+            CmpOp::Le => size <= clause.threshold,
+            CmpOp::Eq => size == clause.threshold,
+            CmpOp::Ge => size >= clause.threshold,
+            CmpOp::Gt => size > clause.threshold,
+        })
+    }
+}
+
+impl SummaryIndex {
+    /// 表示対象になるエントリだけを数える。`--max-depth` には縛られず最後まで辿る。
+    /// シンボリックリンクは辿らない
+    fn build(ctx: &WalkContext<'_>) -> Result<Self> {
+        let root = ctx.root;
+        let mut totals = HashMap::new();
+        let mut stack: Vec<(PathBuf, std::vec::IntoIter<EntryMeta>, DirTotals)> = Vec::new();
+        if let Some(frame) = read_dir_frame(ctx, root, "", 1)? {
+            stack.push((
+                root.to_path_buf(),
+                frame.entries.into_iter(),
+                DirTotals::default(),
+            ));
+        }
+
+        while let Some((_, entries, current)) = stack.last_mut() {
+            let Some(meta) = entries.next() else {
+                let (path, _, done) = stack.pop().unwrap();
+                totals.insert(path, done);
+                if let Some((_, _, parent)) = stack.last_mut() {
+                    parent.add(done);
+                }
+                continue;
+            };
+
+            if let Some(count) = meta.truncated {
+                current.files += count as u64;
+                current.size = current.size.saturating_add(meta.size.unwrap_or(0));
+            } else if meta.is_directory() && !meta.is_symlink {
+                current.dirs += 1;
+                let depth = stack.len() + 1;
+                match read_dir_frame(ctx, &meta.path, "", depth)? {
+                    Some(frame) => {
+                        stack.push((meta.path, frame.entries.into_iter(), DirTotals::default()))
+                    }
+                    None => {
+                        totals.insert(meta.path, DirTotals::default());
+                    }
+                }
+            } else {
+                current.files += 1;
+                current.size = current.size.saturating_add(meta.size.unwrap_or(0));
+            }
+        }
+
+        Ok(Self { totals })
+    }
+}
+
+impl DirTotals {
+    fn add(&mut self, other: DirTotals) {
+        self.files += other.files;
+        self.dirs += other.dirs;
+        self.size = self.size.saturating_add(other.size);
+    }
+}
+
+impl NormalizationCheck {
+    /// NFC にすると同じ名前になる兄弟を警告する
+    fn report(&self, dir: &Path, entries: &[EntryMeta]) {
+        if !self.reported.borrow_mut().insert(dir.to_path_buf()) {
+            return;
+        }
+        let mut groups: HashMap<String, Vec<String>> = HashMap::new();
+        for meta in entries {
+            let name = meta.name.to_string_lossy().into_owned();
+            groups.entry(name.nfc().collect()).or_default().push(name);
+        }
+        let mut clashes: Vec<Vec<String>> = groups
+            .into_values()
+            .filter(|names| names.len() > 1)
+            .collect();
+        clashes.sort();
+        for names in clashes {
+            let listed: Vec<String> = names
+                .iter()
+                .map(|name| {
+                    let form = if is_nfc(name) { "NFC" } else { "non-NFC" };
+                    format!("\"{name}\" ({form})")
+                })
+                .collect();
+            eprintln!(
+                "[warn] names differ only in Unicode normalization in {}: {}",
+                dir.display(),
+                listed.join(", ")
+            );
+        }
+    }
+}
+
+impl DirSizeIndex {
+    /// ルート以下を 1 度だけ走査し、各ディレクトリ配下の通常ファイルのサイズを合計する。
+    /// シンボリックリンクは辿らず、`--hidden` が無ければドットファイルも数えない。
+    fn build(root: &Path, include_hidden: bool, lfs: Option<&LfsStore>) -> Self {
+        let mut totals: HashMap<PathBuf, u64> = HashMap::new();
+        // 先行順で辿る。contents_first と filter_entry を組み合わせると、隠しディレクトリを
+        // 飛ばしたときに親の残りの兄弟まで飛ばされてしまう
+        let walker = WalkDir::new(root)
+            .follow_links(false)
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || include_hidden || !is_hidden(e.file_name()));
+        for entry in walker.flatten() {
+            if entry.file_type().is_dir() {
+                totals.entry(entry.path().to_path_buf()).or_insert(0);
+                continue;
+            }
+            if !entry.file_type().is_file() || entry.depth() == 0 {
+                continue;
+            }
+            let len = entry.metadata().map(|md| md.len()).unwrap_or(0);
+            let size = match lfs.filter(|_| len <= LFS_POINTER_MAX) {
+                Some(store) => store
+                    .read_pointer(entry.path())
+                    .map_or(len, |pointer| pointer.size),
+                None => len,
+            };
+            for ancestor in entry.path().ancestors().skip(1) {
+                *totals.entry(ancestor.to_path_buf()).or_insert(0) += size;
+                if ancestor == root {
+                    break;
+                }
+            }
+        }
+        Self { totals }
+    }
+
+    /// `--rev` / `--source index` 用。ファイルシステムの代わりに git のツリーから同じ合計を求める
+    fn from_snapshot(snapshot: &GitSnapshot, root: &Path, include_hidden: bool) -> Self {
+        let mut totals: HashMap<PathBuf, u64> = HashMap::new();
+        Self::snapshot_total(snapshot, root, include_hidden, &mut totals);
+        Self { totals }
+    }
+
+    fn snapshot_total(
+        snapshot: &GitSnapshot,
+        dir: &Path,
+        include_hidden: bool,
+        totals: &mut HashMap<PathBuf, u64>,
+    ) -> u64 {
+        let Some(children) = snapshot.children(dir) else {
+            return 0;
+        };
+        let mut total = 0u64;
+        for meta in children {
+            if !include_hidden && is_hidden(&meta.name) {
+                continue;
+            }
+            let size = match meta.git_kind {
+                Some(EntryKind::Dir) => {
+                    Self::snapshot_total(snapshot, &meta.path, include_hidden, totals)
+                }
+                Some(EntryKind::File) => meta.size.unwrap_or(0),
+                _ => 0,
+            };
+            total = total.saturating_add(size);
+        }
+        totals.insert(dir.to_path_buf(), total);
+        total
+    }
+
+    fn total_for(&self, path: &Path) -> Option<u64> {
+        self.totals.get(path).copied()
+    }
+}
+
+impl TimeFilter {
+    fn allows(&self, time: Option<SystemTime>) -> bool {
+        let Some(time) = time else {
+            return false;
+        };
+        if let Some(lower) = self.lower {
+            let ok = if lower.inclusive {
+                time >= lower.at
+            } else {
+                time > lower.at
+            };
+            if !ok {
+                return false;
+            }
+        }
+        if let Some(upper) = self.upper {
+            let ok = if upper.inclusive {
+                time <= upper.at
+            } else {
+                time < upper.at
+            };
+            if !ok {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+impl PermFilter {
+    fn allows(&self, perm: Option<u32>) -> bool {
+        let Some(mode) = perm else {
+            return false;
+        };
+        let mode = mode & 0o7777;
+        match self.mode {
+            PermMatch::Exact => mode == self.bits,
+            PermMatch::All => mode & self.bits == self.bits,
+            // find と同じく `/000` はすべてに一致する
+            PermMatch::Any => self.bits == 0 || mode & self.bits != 0,
+        }
+    }
+}
+
+fn split_cmp_prefix(spec: &str) -> Option<(CmpOp, &str)> {
+    if let Some(rest) = spec.strip_prefix(">=") {
+        Some((CmpOp::Ge, rest))
+    } else if let Some(rest) = spec.strip_prefix("<=") {
+        Some((CmpOp::Le, rest))
+    } else if let Some(rest) = spec.strip_prefix("==") {
+        Some((CmpOp::Eq, rest))
+    } else if let Some(rest) = spec.strip_prefix('>') {
+        Some((CmpOp::Gt, rest))
+    } else {
+        spec.strip_prefix('<').map(|rest| (CmpOp::Lt, rest))
+    }
+}
+
+/// サイズ条件を解析する。`>1MB` のような比較、`10k..1M` のような範囲（両端を含む）、
+/// それらをカンマで並べた複数条件（AND）を受け付ける。
+fn parse_size_filter(spec: &str, flag: &str) -> Result<SizeFilter> {
+    let spec = spec.trim();
+    let mut clauses = Vec::new();
+    for clause in spec.split(',') {
+        let clause = clause.trim();
+        if clause.is_empty() {
+            return Err(anyhow!("invalid {flag} value: {spec}"));
+        }
+
+        if let Some((from, to)) = clause.split_once("..") {
+            let (from, to) = (from.trim(), to.trim());
+            if from.is_empty() && to.is_empty() {
+                return Err(anyhow!("invalid {flag} value: {spec}"));
+            }
+            if !from.is_empty() {
+                clauses.push(SizeClause {
+                    cmp: CmpOp::Ge,
+                    threshold: parse_size_value(from, flag)?,
+                });
+            }
+            if !to.is_empty() {
+                clauses.push(SizeClause {
+                    cmp: CmpOp::Le,
+                    threshold: parse_size_value(to, flag)?,
+                });
+            }
+            continue;
+        }
+
+        let Some((cmp, remainder)) = split_cmp_prefix(clause) else {
+            return Err(anyhow!("invalid {flag} value: {spec}"));
+        };
+        clauses.push(SizeClause {
+            cmp,
+            threshold: parse_size_value(remainder.trim(), flag)?,
+        });
+    }
+
+    Ok(SizeFilter { clauses })
+}
+
+fn parse_size_value(value: &str, flag: &str) -> Result<u64> {
+    if value.is_empty() {
+        return Err(anyhow!("invalid {flag} value: {value}"));
     }
 
-    let mut split_idx = remainder.len();
-    for (idx, ch) in remainder.char_indices() {
+    let mut split_idx = value.len();
+    for (idx, ch) in value.char_indices() {
         if !ch.is_ascii_digit() {
             split_idx = idx;
             break;
         }
     }
 
-    let (num_part, unit_part) = remainder.split_at(split_idx);
+    let (num_part, unit_part) = value.split_at(split_idx);
     if num_part.is_empty() {
-        return Err(anyhow!("invalid --filter-size value: {spec}"));
+        return Err(anyhow!("invalid {flag} value: {value}"));
     }
-    let value: u64 = num_part
+    let number: u64 = num_part
         .parse()
-        .map_err(|_| anyhow!("invalid --filter-size numeric value: {spec}"))?;
+        .map_err(|_| anyhow!("invalid {flag} numeric value: {value}"))?;
 
     let unit = unit_part.trim().to_ascii_lowercase();
     let multiplier: u64 = match unit.as_str() {
@@ -586,60 +2512,233 @@ fn parse_size_filter(spec: &str) -> Result<SizeFilter> {
         "m" | "mb" | "mib" => 1 << 20,
         "g" | "gb" | "gib" => 1 << 30,
         "t" | "tb" | "tib" => 1 << 40,
-        _ => return Err(anyhow!("invalid --filter-size unit: {spec}")),
+        _ => return Err(anyhow!("invalid {flag} unit: {value}")),
     };
 
-    let threshold = value
+    number
         .checked_mul(multiplier)
-        .ok_or_else(|| anyhow!("--filter-size value overflow: {spec}"))?;
-
-    Ok(SizeFilter { cmp, threshold })
+        .ok_or_else(|| anyhow!("{flag} value overflow: {value}"))
 }
 
-fn parse_mtime_filter(spec: &str) -> Result<MtimeFilter> {
+/// 時刻フィルタを解析する。
+///
+/// - `3d` : 3 日以内に更新（従来の書式）
+/// - `>30d` / `<=2h` : 経過時間で比較（`>` は「より古い」）
+/// - `>=2025-01-01` / `<2025-01-01T12:00` : 絶対日時で比較（`>` は「より後」）
+/// - `2025-01-01` : その日 1 日分
+/// - `2025-01-01..2025-03-31` / `30d..7d` : 範囲（両端を含む）
+fn parse_time_filter(spec: &str, flag: &str, now: SystemTime) -> Result<TimeFilter> {
     let spec = spec.trim();
     if spec.is_empty() {
-        return Err(anyhow!("invalid --filter-mtime value"));
+        return Err(anyhow!("invalid {flag} value"));
+    }
+
+    if let Some((from, to)) = spec.split_once("..") {
+        let a = parse_time_point(from.trim(), flag, now)?;
+        let b = parse_time_point(to.trim(), flag, now)?;
+        // 上端は後ろ側の指定から決める。日付ならその日の終わりまで、時点ならその時点を含む
+        let last = if a.end >= b.end { a } else { b };
+        return Ok(TimeFilter {
+            lower: Some(TimeBound {
+                at: a.start.min(b.start),
+                inclusive: true,
+            }),
+            upper: Some(span_upper_bound(last)),
+        });
+    }
+
+    let (op, rest) = match split_cmp_prefix(spec) {
+        Some((op, rest)) => (Some(op), rest),
+        None => (None, spec),
+    };
+    let rest = rest.trim();
+
+    if let Some(age) = parse_age(rest, flag)? {
+        let at = now.checked_sub(age).unwrap_or(UNIX_EPOCH);
+        let bound = |inclusive| TimeBound { at, inclusive };
+        // 経過時間の比較は時刻の大小と逆向きになる
+        let filter = match op {
+            None | Some(CmpOp::Le) => TimeFilter {
+                lower: Some(bound(true)),
+                upper: None,
+            },
+            Some(CmpOp::Lt) => TimeFilter {
+                lower: Some(bound(false)),
+                upper: None,
+            },
+            Some(CmpOp::Ge) => TimeFilter {
+                lower: None,
+                upper: Some(bound(true)),
+            },
+            Some(CmpOp::Gt) => TimeFilter {
+                lower: None,
+                upper: Some(bound(false)),
+            },
+            Some(CmpOp::Eq) => {
+                return Err(anyhow!("invalid {flag} value: {spec} (== needs a date)"))
+            }
+        };
+        return Ok(filter);
+    }
+
+    let span = parse_time_point(rest, flag, now)?;
+    let filter = match op {
+        None | Some(CmpOp::Eq) => TimeFilter {
+            lower: Some(TimeBound {
+                at: span.start,
+                inclusive: true,
+            }),
+            upper: Some(span_upper_bound(span)),
+        },
+        Some(CmpOp::Ge) => TimeFilter {
+            lower: Some(TimeBound {
+                at: span.start,
+                inclusive: true,
+            }),
+            upper: None,
+        },
+        Some(CmpOp::Gt) => TimeFilter {
+            lower: Some(TimeBound {
+                at: span.end,
+                inclusive: span.start != span.end,
+            }),
+            upper: None,
+        },
+        Some(CmpOp::Le) => TimeFilter {
+            lower: None,
+            upper: Some(span_upper_bound(span)),
+        },
+        Some(CmpOp::Lt) => TimeFilter {
+            lower: None,
+            upper: Some(TimeBound {
+                at: span.start,
+                inclusive: false,
+            }),
+        },
+    };
+    Ok(filter)
+}
+
+fn span_upper_bound(span: TimeSpan) -> TimeBound {
+    TimeBound {
+        at: span.end,
+        inclusive: span.start == span.end,
+    }
+}
+
+fn parse_time_point(value: &str, flag: &str, now: SystemTime) -> Result<TimeSpan> {
+    if let Some(age) = parse_age(value, flag)? {
+        let at = now.checked_sub(age).unwrap_or(UNIX_EPOCH);
+        return Ok(TimeSpan { start: at, end: at });
     }
+    parse_absolute_time(value)
+        .ok_or_else(|| anyhow!("invalid {flag} value: {value} (expected e.g. 3d or 2025-01-01)"))
+}
+
+/// `--since 90d` / `--since 2025-01-01` のような起点。日付はその日の始まり
+pub(crate) fn parse_time_start(value: &str, flag: &str) -> Result<SystemTime> {
+    parse_time_point(value, flag, SystemTime::now()).map(|span| span.start)
+}
 
-    let mut split_idx = spec.len();
-    for (idx, ch) in spec.char_indices() {
+/// `3d` や `10m` のような経過時間。数字で始まらない場合は `None`。
+fn parse_age(value: &str, flag: &str) -> Result<Option<Duration>> {
+    let mut split_idx = value.len();
+    for (idx, ch) in value.char_indices() {
         if !ch.is_ascii_digit() {
             split_idx = idx;
             break;
         }
     }
 
-    let (num_part, unit_part) = spec.split_at(split_idx);
+    let (num_part, unit_part) = value.split_at(split_idx);
     if num_part.is_empty() {
-        return Err(anyhow!("invalid --filter-mtime value: {spec}"));
+        return Err(anyhow!("invalid {flag} value: {value}"));
+    }
+    // 日付 (2025-01-01) は数字の直後に '-' が続く
+    if unit_part.starts_with(['-', '/', 'T', ':']) {
+        return Ok(None);
     }
     let quantity: u64 = num_part
         .parse()
-        .map_err(|_| anyhow!("invalid --filter-mtime value: {spec}"))?;
+        .map_err(|_| anyhow!("invalid {flag} value: {value}"))?;
 
     let unit = unit_part.trim().to_ascii_lowercase();
-    let seconds = match unit.as_str() {
-        "s" | "sec" | "secs" => quantity,
-        "m" | "min" | "mins" => quantity
-            .checked_mul(60u64)
-            .ok_or_else(|| anyhow!("invalid --filter-mtime value: {spec}"))?,
-        "h" | "hour" | "hours" => quantity
-            .checked_mul(60u64 * 60u64)
-            .ok_or_else(|| anyhow!("invalid --filter-mtime value: {spec}"))?,
-        "d" | "day" | "days" => quantity
-            .checked_mul(60u64 * 60u64 * 24u64)
-            .ok_or_else(|| anyhow!("invalid --filter-mtime value: {spec}"))?,
-        "w" | "week" | "weeks" => quantity
-            .checked_mul(60u64 * 60u64 * 24u64 * 7u64)
-            .ok_or_else(|| anyhow!("invalid --filter-mtime value: {spec}"))?,
-        _ => return Err(anyhow!("invalid --filter-mtime unit: {spec}")),
+    let multiplier: u64 = match unit.as_str() {
+        "s" | "sec" | "secs" => 1,
+        "m" | "min" | "mins" => 60,
+        "h" | "hour" | "hours" => 60 * 60,
+        "d" | "day" | "days" => 60 * 60 * 24,
+        "w" | "week" | "weeks" => 60 * 60 * 24 * 7,
+        _ => return Err(anyhow!("invalid {flag} unit: {value}")),
     };
+    let seconds = quantity
+        .checked_mul(multiplier)
+        .ok_or_else(|| anyhow!("invalid {flag} value: {value}"))?;
+    Ok(Some(Duration::from_secs(seconds)))
+}
+
+/// 日付はローカルタイムゾーンの 1 日分、日時はその瞬間として解釈する。
+fn parse_absolute_time(value: &str) -> Option<TimeSpan> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        let at = SystemTime::from(dt.with_timezone(&Utc));
+        return Some(TimeSpan { start: at, end: at });
+    }
+
+    for fmt in [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M",
+        "%Y-%m-%d %H:%M",
+    ] {
+        if let Ok(naive) = NaiveDateTime::parse_from_str(value, fmt) {
+            let at = local_to_system_time(naive)?;
+            return Some(TimeSpan { start: at, end: at });
+        }
+    }
+
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .or_else(|_| NaiveDate::parse_from_str(value, "%Y/%m/%d"))
+        .ok()?;
+    let start = local_to_system_time(date.and_hms_opt(0, 0, 0)?)?;
+    let end = local_to_system_time(date.succ_opt()?.and_hms_opt(0, 0, 0)?)?;
+    Some(TimeSpan { start, end })
+}
+
+fn local_to_system_time(naive: NaiveDateTime) -> Option<SystemTime> {
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(|dt| SystemTime::from(dt.with_timezone(&Utc)))
+}
+
+fn convenience_perm_filters(cli: &Cli) -> Vec<PermFilter> {
+    let mut filters = Vec::new();
+    if cli.world_writable {
+        filters.push(PermFilter {
+            mode: PermMatch::All,
+            bits: 0o002,
+        });
+    }
+    if cli.executable {
+        filters.push(PermFilter {
+            mode: PermMatch::Any,
+            bits: 0o111,
+        });
+    }
+    if cli.setuid {
+        filters.push(PermFilter {
+            mode: PermMatch::All,
+            bits: 0o4000,
+        });
+    }
+
+    #[cfg(windows)]
+    if !filters.is_empty() {
+        eprintln!("[warn] --world-writable/--executable/--setuid ignored on Windows");
+        filters.clear();
+    }
 
-    let duration = Duration::from_secs(seconds);
-    let now = SystemTime::now();
-    let earliest = now.checked_sub(duration).unwrap_or(UNIX_EPOCH);
-    Ok(MtimeFilter { earliest })
+    filters
 }
 
 fn parse_perm_filter(spec: &str) -> Result<Option<PermFilter>> {
@@ -657,18 +2756,124 @@ fn parse_perm_filter(spec: &str) -> Result<Option<PermFilter>> {
 
     #[cfg(unix)]
     {
-        if !trimmed.chars().all(|c| c.is_ascii_digit()) {
-            return Err(anyhow!("invalid --filter-perm value: {trimmed}"));
+        parse_perm_spec(trimmed).map(Some)
+    }
+}
+
+/// find(1) の `-perm` 相当の書式を解析する。
+///
+/// - `755` / `4755` : 特殊ビットを含めて完全一致
+/// - `-mode` : 指定ビットをすべて含む
+/// - `/mode` : 指定ビットのいずれかを含む
+/// - `u+x` / `o+w` / `ug+rw,+t` : シンボリック指定（接頭辞なしは `-` と同じ）
+/// - `u=rwx,go=rx` : `=` を含むシンボリック指定は、接頭辞なしなら find と同じく完全一致
+#[cfg_attr(windows, allow(dead_code))]
+fn parse_perm_spec(spec: &str) -> Result<PermFilter> {
+    let (prefix, body) = if let Some(rest) = spec.strip_prefix('-') {
+        (Some(PermMatch::All), rest)
+    } else if let Some(rest) = spec.strip_prefix('/') {
+        (Some(PermMatch::Any), rest)
+    } else {
+        (None, spec)
+    };
+
+    if !body.is_empty() && body.chars().all(|c| c.is_ascii_digit()) {
+        if body.len() < 3 || body.len() > 4 {
+            return Err(anyhow!("invalid --filter-perm value: {spec}"));
+        }
+        let bits = u32::from_str_radix(body, 8)
+            .map_err(|_| anyhow!("invalid --filter-perm value: {spec}"))?;
+        return Ok(PermFilter {
+            mode: prefix.unwrap_or(PermMatch::Exact),
+            bits: bits & 0o7777,
+        });
+    }
+
+    let bits =
+        parse_symbolic_perm(body).ok_or_else(|| anyhow!("invalid --filter-perm value: {spec}"))?;
+    let bare = if body.contains('=') {
+        PermMatch::Exact
+    } else {
+        PermMatch::All
+    };
+    Ok(PermFilter {
+        mode: prefix.unwrap_or(bare),
+        bits,
+    })
+}
+
+/// chmod と同じく 000 から順に節を適用したビットを返す。`=` は対象クラスのビットを置き換える
+fn parse_symbolic_perm(spec: &str) -> Option<u32> {
+    let mut bits = 0;
+    for clause in spec.split(',') {
+        let op = clause.find(['+', '='])?;
+        let (who, perms) = (&clause[..op], &clause[op + 1..]);
+        let assign = clause[op..].starts_with('=');
+        // `o=` のように `=` だけは権限なしを指定できる
+        if perms.is_empty() && !assign {
+            return None;
         }
-        if trimmed.len() < 3 || trimmed.len() > 4 {
-            return Err(anyhow!("invalid --filter-perm value: {trimmed}"));
+        let mut classes = 0u8;
+        for c in who.chars() {
+            classes |= match c {
+                'u' => 0b100,
+                'g' => 0b010,
+                'o' => 0b001,
+                'a' => 0b111,
+                _ => return None,
+            };
+        }
+        if classes == 0 {
+            classes = 0b111;
+        }
+        let (user, group, other) = (
+            classes & 0b100 != 0,
+            classes & 0b010 != 0,
+            classes & 0b001 != 0,
+        );
+        if assign {
+            if user {
+                bits &= !0o4700;
+            }
+            if group {
+                bits &= !0o2070;
+            }
+            if other {
+                bits &= !0o1007;
+            }
+        }
+        for p in perms.chars() {
+            let rwx = match p {
+                'r' => 0o4,
+                'w' => 0o2,
+                'x' => 0o1,
+                's' => {
+                    if user {
+                        bits |= 0o4000;
+                    }
+                    if group {
+                        bits |= 0o2000;
+                    }
+                    continue;
+                }
+                't' => {
+                    bits |= 0o1000;
+                    continue;
+                }
+                _ => return None,
+            };
+            if user {
+                bits |= rwx << 6;
+            }
+            if group {
+                bits |= rwx << 3;
+            }
+            if other {
+                bits |= rwx;
+            }
         }
-        let value = u32::from_str_radix(trimmed, 8)
-            .map_err(|_| anyhow!("invalid --filter-perm value: {trimmed}"))?;
-        Ok(Some(PermFilter {
-            expected: value & 0o777,
-        }))
     }
+    Some(bits)
 }
 
 impl EntryMeta {
@@ -679,10 +2884,7 @@ impl EntryMeta {
             .unwrap_or_else(|| path.as_os_str().to_owned());
         let mut errors = Vec::new();
         let metadata_symlink = fs::symlink_metadata(path)
-            .map_err(|err| {
-                errors.push(err.to_string());
-                err
-            })
+            .inspect_err(|err| errors.push(err.to_string()))
             .ok();
 
         let mut file_type = metadata_symlink.as_ref().map(|m| m.file_type());
@@ -690,10 +2892,7 @@ impl EntryMeta {
 
         let metadata_follow = if is_symlink {
             fs::metadata(path)
-                .map_err(|err| {
-                    errors.push(err.to_string());
-                    err
-                })
+                .inspect_err(|err| errors.push(err.to_string()))
                 .ok()
         } else {
             metadata_symlink.clone()
@@ -723,6 +2922,8 @@ impl EntryMeta {
     ) -> Self {
         let mut size = None;
         let mut mtime = None;
+        let mut ctime = None;
+        let mut atime = None;
         #[cfg_attr(windows, allow(unused_mut))]
         let mut perm_unix = None;
         #[cfg_attr(not(windows), allow(unused_mut))]
@@ -732,6 +2933,8 @@ impl EntryMeta {
         if let Some(md) = metadata.as_ref() {
             size = Some(md.len());
             mtime = md.modified().ok();
+            ctime = change_time(md);
+            atime = md.accessed().ok();
             target_file_type = Some(md.file_type());
             #[cfg(unix)]
             {
@@ -775,6 +2978,8 @@ impl EntryMeta {
             target_file_type,
             size,
             mtime,
+            ctime,
+            atime,
             perm_unix,
             perm_win,
             is_symlink,
@@ -787,11 +2992,127 @@ impl EntryMeta {
                 Some(errors.join("; "))
             },
             git_status: None,
+            git_changes: None,
+            rename: None,
+            submodule: None,
+            lfs: None,
+            last_commit: None,
+            churn: None,
+            git_kind: None,
+            stage: None,
+            ghost: false,
+            truncated: None,
+            totals: None,
+        }
+    }
+
+    /// 省略したエントリをまとめた要約エントリ。サイズはディレクトリなら再帰合計を使う
+    fn truncated_summary(
+        dir: &Path,
+        omitted: &[EntryMeta],
+        dir_sizes: Option<&DirSizeIndex>,
+        git: &GitTracker,
+        include_hidden: bool,
+    ) -> Self {
+        let size = omitted
+            .iter()
+            .map(|meta| {
+                if meta.is_directory() && !meta.is_symlink {
+                    dir_sizes
+                        .and_then(|index| index.total_for(&meta.path))
+                        .unwrap_or_else(|| {
+                            let index = match &git.snapshot {
+                                Some(snapshot) => DirSizeIndex::from_snapshot(
+                                    snapshot,
+                                    &meta.path,
+                                    include_hidden,
+                                ),
+                                None => DirSizeIndex::build(
+                                    &meta.path,
+                                    include_hidden,
+                                    git.lfs.as_ref(),
+                                ),
+                            };
+                            index.total_for(&meta.path).unwrap_or(0)
+                        })
+                } else if meta.points_to_directory() {
+                    0
+                } else {
+                    meta.size.unwrap_or(0)
+                }
+            })
+            .fold(0u64, u64::saturating_add);
+        let count = omitted.len();
+        let noun = if count == 1 { "entry" } else { "entries" };
+
+        Self {
+            path: dir.to_path_buf(),
+            name: OsString::from(format!("… {count} more {noun}")),
+            file_type: None,
+            target_file_type: None,
+            size: Some(size),
+            mtime: None,
+            ctime: None,
+            atime: None,
+            perm_unix: None,
+            perm_win: None,
+            is_symlink: false,
+            symlink_target: None,
+            canonical_path: None,
+            loop_detected: false,
+            error: None,
+            git_status: None,
+            git_changes: None,
+            rename: None,
+            submodule: None,
+            lfs: None,
+            last_commit: None,
+            churn: None,
+            git_kind: None,
+            stage: None,
+            ghost: false,
+            truncated: Some(count),
+            totals: None,
+        }
+    }
+
+    /// git のツリーやインデックスから作るエントリ。ファイルシステムのメタデータは持たない
+    fn from_git(path: PathBuf, name: OsString, kind: EntryKind) -> Self {
+        Self {
+            path,
+            name,
+            file_type: None,
+            target_file_type: None,
+            size: None,
+            mtime: None,
+            ctime: None,
+            atime: None,
+            perm_unix: None,
+            perm_win: None,
+            is_symlink: false,
+            symlink_target: None,
+            canonical_path: None,
+            loop_detected: false,
+            error: None,
+            git_status: None,
+            git_changes: None,
+            rename: None,
+            submodule: None,
+            lfs: None,
+            last_commit: None,
+            churn: None,
+            git_kind: Some(kind),
+            stage: None,
+            ghost: false,
+            truncated: None,
+            totals: None,
         }
     }
 
     fn is_directory(&self) -> bool {
-        self.file_type.map(|ft| ft.is_dir()).unwrap_or(false)
+        self.file_type
+            .map(|ft| ft.is_dir())
+            .unwrap_or(matches!(self.git_kind, Some(EntryKind::Dir)))
     }
 
     fn points_to_directory(&self) -> bool {
@@ -828,10 +3149,7 @@ impl EntryMeta {
         }
 
         let metadata = fs::metadata(&path)
-            .map_err(|err| {
-                errors.push(err.to_string());
-                err
-            })
+            .inspect_err(|err| errors.push(err.to_string()))
             .ok();
 
         if file_type.is_none() {
@@ -846,6 +3164,24 @@ impl EntryMeta {
     }
 }
 
+/// UNIX では inode 変更時刻、それ以外では作成時刻を ctime として扱う
+#[cfg(unix)]
+fn change_time(md: &Metadata) -> Option<SystemTime> {
+    use std::os::unix::fs::MetadataExt;
+    let secs = md.ctime();
+    let nanos = u32::try_from(md.ctime_nsec()).unwrap_or(0);
+    if secs >= 0 {
+        UNIX_EPOCH.checked_add(Duration::new(secs as u64, nanos))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(secs.unsigned_abs()))
+    }
+}
+
+#[cfg(not(unix))]
+fn change_time(md: &Metadata) -> Option<SystemTime> {
+    md.created().ok()
+}
+
 #[cfg(unix)]
 fn format_permissions(meta: &EntryMeta) -> Option<String> {
     let _ = meta.perm_win;
@@ -907,7 +3243,11 @@ fn format_windows_attributes(attrs: u32) -> String {
 
 impl Entry {
     fn from_meta(meta: &EntryMeta, depth: usize) -> Self {
-        let kind = if meta.is_symlink {
+        let kind = if meta.truncated.is_some() {
+            EntryKind::Truncated
+        } else if let Some(kind) = meta.git_kind {
+            kind
+        } else if meta.is_symlink {
             EntryKind::Symlink
         } else if meta.is_directory() {
             EntryKind::Dir
@@ -940,13 +3280,28 @@ impl Entry {
             path: meta.path.display().to_string(),
             depth,
             kind,
-            size: meta.size,
+            size: meta.totals.map(|t| t.size).or(meta.size),
             mtime,
             perm,
             symlink_target,
             loop_detected: meta.loop_detected,
             error: meta.error.clone(),
             git_status: meta.git_status,
+            git_changes: meta.git_changes,
+            renamed_from: meta
+                .rename
+                .as_ref()
+                .map(|rename| rename.from.display().to_string()),
+            similarity: meta.rename.as_ref().and_then(|rename| rename.similarity),
+            submodule: meta.submodule,
+            ghost: meta.ghost,
+            stage: meta.stage,
+            last_commit: meta.last_commit.as_deref().cloned(),
+            churn: meta.churn,
+            lfs: meta.lfs.clone(),
+            truncated: meta.truncated,
+            files: meta.totals.map(|t| t.files),
+            dirs: meta.totals.map(|t| t.dirs),
         }
     }
 }
@@ -1014,7 +3369,7 @@ fn handle_entry_with_guard(
         }
     }
 
-    if let Some(maxd) = cli.max_depth {
+    if let Some(maxd) = print_depth_limit(cli) {
         if depth >= maxd {
             descend = false;
         }
@@ -1114,25 +3469,34 @@ impl<W: WriteColor> WriteColor for EncodingWriter<W> {
 // ---------------------------------------------------------------------
 // PLAIN 出力モード
 // ---------------------------------------------------------------------
-fn run_tree_plain(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
+fn run_tree_plain(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
     let mut out = make_encoded_writer(cli);
+    let mut budget = OutputBudget::new(cli);
+    let mut stats = TreeStats::new();
     let mut bold = ColorSpec::new();
     bold.set_bold(true);
     out.set_color(&bold)?;
     writeln!(&mut out, "{}", root.display())?;
     out.reset()?;
+    if budget.tracks_bytes() {
+        budget.charge_fixed(root.display().to_string().len() + 1);
+        let trailer = budget.worst_trailer(|_, limit| plain_trailer(limit).len());
+        budget.reserve(trailer);
+        if !cli.no_report {
+            budget.reserve(plain_footer(u64::MAX, u64::MAX).len());
+        }
+    }
 
-    let mut root_meta = EntryMeta::from_path(root);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
 
@@ -1143,26 +3507,12 @@ fn run_tree_plain(
         visited.insert(root.to_path_buf());
     }
 
-    if !root_meta.points_to_directory() || matches!(cli.max_depth, Some(1)) {
-        return Ok(());
-    }
-
     let mut stack: Vec<Frame> = Vec::new();
     let mut pending_dirs: Vec<PlainPending> = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        root,
-        "",
-        1,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
-        stack.push(frame);
+    if root_meta.points_to_directory() && !root_only(cli) {
+        if let Some(frame) = read_dir_frame(ctx, root, "", 1)? {
+            stack.push(frame);
+        }
     }
 
     let mut depth_warned = false;
@@ -1172,7 +3522,7 @@ fn run_tree_plain(
         if frame.idx >= frame.entries.len() {
             stack.pop();
             if let Some(pending) = pending_dirs.pop() {
-                finalize_pending_dir(out.as_mut(), pending, &mut pending_dirs)?;
+                finalize_pending_dir(out.as_mut(), pending, &mut pending_dirs, &mut budget)?;
             }
             continue;
         }
@@ -1193,30 +3543,43 @@ fn run_tree_plain(
         );
 
         if descend {
+            let reserved = if budget.tracks_bytes() {
+                let mut bound = entry.clone();
+                bound.size = Some(u64::MAX);
+                plain_line_len(&frame.prefix, &bound, is_last)
+            } else {
+                0
+            };
+            if !budget.admit(reserved) {
+                break;
+            }
+            stats.record(&entry);
             let child_path = entry_meta.path.clone();
-            let pending_entry = PlainPending::new(entry, frame.prefix.clone(), is_last);
-            match read_dir_frame(
-                root,
-                &child_path,
-                &child_prefix,
-                frame.depth + 1,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-            )? {
+            let pending_entry = PlainPending::new(entry, frame.prefix.clone(), is_last, reserved);
+            match read_dir_frame(ctx, &child_path, &child_prefix, frame.depth + 1)? {
                 Some(child_frame) => {
                     pending_dirs.push(pending_entry);
                     stack.push(child_frame);
                 }
                 None => {
-                    finalize_pending_dir(out.as_mut(), pending_entry, &mut pending_dirs)?;
+                    finalize_pending_dir(
+                        out.as_mut(),
+                        pending_entry,
+                        &mut pending_dirs,
+                        &mut budget,
+                    )?;
                 }
             }
         } else {
+            let cost = if budget.tracks_bytes() {
+                plain_line_len(&frame.prefix, &entry, is_last)
+            } else {
+                0
+            };
+            if !budget.admit(cost) {
+                break;
+            }
+            stats.record(&entry);
             if !depth_warned
                 && cli.warn_depth > 0
                 && pending_dirs.len() + stack_len > cli.warn_depth
@@ -1238,15 +3601,48 @@ fn run_tree_plain(
         }
     }
 
-    Ok(())
+    // 打ち切った場合も、開いたままのディレクトリ行は書き切ってからトレーラを付ける
+    while let Some(pending) = pending_dirs.pop() {
+        finalize_pending_dir(out.as_mut(), pending, &mut pending_dirs, &mut budget)?;
+    }
+    if let Some(limit) = budget.exhausted() {
+        write!(&mut out, "{}", plain_trailer(limit))?;
+    }
+    if !cli.no_report {
+        write!(&mut out, "{}", stats.footer())?;
+    }
+    if wants_stats(cli) {
+        if cli.no_report {
+            writeln!(&mut out)?;
+        }
+        stats.write_plain_report(out.as_mut())?;
+    }
+
+    Ok(budget.completion())
+}
+
+fn plain_trailer(limit: BudgetLimit) -> String {
+    format!("… output truncated ({})\n", limit.describe())
+}
+
+/// 色を除いた 1 行分のバイト数
+fn plain_line_len(prefix: &str, entry: &Entry, is_last: bool) -> usize {
+    let mut buf = termcolor::NoColor::new(Vec::new());
+    let _ = write_plain_entry(&mut buf, prefix, entry, is_last);
+    buf.into_inner().len()
 }
 
 fn finalize_pending_dir(
     out: &mut dyn WriteColor,
     mut pending: PlainPending,
-    pending_dirs: &mut Vec<PlainPending>,
+    pending_dirs: &mut [PlainPending],
+    budget: &mut OutputBudget,
 ) -> io::Result<()> {
     pending.entry.size = Some(pending.total_size);
+    if budget.tracks_bytes() {
+        let actual = plain_line_len(&pending.prefix, &pending.entry, pending.is_last);
+        budget.refund(pending.reserved.saturating_sub(actual));
+    }
     finalize_plain_entry(
         out,
         pending.entry,
@@ -1261,7 +3657,7 @@ fn finalize_plain_entry(
     entry: Entry,
     prefix: &str,
     is_last: bool,
-    pending_dirs: &mut Vec<PlainPending>,
+    pending_dirs: &mut [PlainPending],
 ) -> io::Result<()> {
     if let Some(size) = entry.size {
         if let Some(parent) = pending_dirs.last_mut() {
@@ -1280,46 +3676,124 @@ fn write_plain_entry(
     let connector = if is_last { "└── " } else { "├── " };
     write!(out, "{}{}", prefix, connector)?;
 
+    if matches!(entry.kind, EntryKind::Truncated) {
+        let size = format_size_human(entry.size.unwrap_or(0));
+        writeln!(out, "{} ({size})", entry.name)?;
+        return Ok(());
+    }
+
     if let Some(status) = entry.git_status {
-        if let Some(color) = match status {
-            'M' => Some(Color::Yellow),
-            'A' => Some(Color::Green),
-            'D' => Some(Color::Red),
-            'R' => Some(Color::Cyan),
-            _ => None,
-        } {
+        write!(out, "[")?;
+        write_git_status_column(out, status.index, git_index_color(status))?;
+        write_git_status_column(out, status.worktree, git_worktree_color(status))?;
+        write!(out, "] ")?;
+    } else if let Some(changes) = entry.git_changes {
+        write!(out, "[")?;
+        for (idx, (symbol, count)) in changes.entries().enumerate() {
+            if idx > 0 {
+                write!(out, " ")?;
+            }
             let mut spec = ColorSpec::new();
-            spec.set_fg(Some(color));
+            spec.set_fg(git_rollup_color(symbol));
             out.set_color(&spec)?;
+            write!(out, "{symbol}{count}")?;
+            out.reset()?;
         }
-        write!(out, "[{status}] ")?;
-        out.reset()?;
+        write!(out, "] ")?;
     }
 
     if let Some(size) = entry.size {
         write!(out, "[{size}] ")?;
     }
 
+    let mut spec = ColorSpec::new();
     match entry.kind {
         EntryKind::Dir => {
-            let mut spec = ColorSpec::new();
             spec.set_fg(Some(Color::Blue));
-            out.set_color(&spec)?;
         }
         EntryKind::Symlink => {
-            let mut spec = ColorSpec::new();
             spec.set_fg(Some(Color::Cyan));
-            out.set_color(&spec)?;
         }
         _ => {}
     }
+    // 削除済みのエントリは淡色で出す
+    spec.set_dimmed(entry.ghost);
+    if !spec.is_none() {
+        out.set_color(&spec)?;
+    }
 
     write!(out, "{}", entry.name)?;
     out.reset()?;
 
+    if let (Some(files), Some(dirs)) = (entry.files, entry.dirs) {
+        write!(out, "  ({files} files, {dirs} dirs)")?;
+    }
+
+    if let Some(from) = &entry.renamed_from {
+        write!(out, " ← {from}")?;
+        if let Some(similarity) = entry.similarity {
+            write!(out, " ({similarity}%)")?;
+        }
+    }
     if let Some(target) = &entry.symlink_target {
         write!(out, " -> {}", target)?;
     }
+    if let Some(lfs) = &entry.lfs {
+        write!(out, "  ")?;
+        if lfs.local {
+            write!(out, "[lfs {}]", &lfs.oid[..7])?;
+        } else {
+            let mut spec = ColorSpec::new();
+            spec.set_fg(Some(Color::Yellow));
+            out.set_color(&spec)?;
+            write!(out, "[lfs {}, not downloaded]", &lfs.oid[..7])?;
+            out.reset()?;
+        }
+    }
+    if let Some(submodule) = entry.submodule {
+        write!(out, "  ")?;
+        if !submodule.is_clean() {
+            let mut spec = ColorSpec::new();
+            spec.set_fg(Some(Color::Yellow));
+            out.set_color(&spec)?;
+        }
+        write!(out, "[submodule: {submodule}]")?;
+        out.reset()?;
+    }
+    // ステージ 0 は通常のエントリなので、衝突中のものだけ印を付ける
+    if let Some(stage @ 1..=3) = entry.stage {
+        write!(out, "  ")?;
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(Color::Red));
+        out.set_color(&spec)?;
+        write!(out, "[stage {stage}: {}]", stage_label(stage))?;
+        out.reset()?;
+    }
+    if let Some(commit) = &entry.last_commit {
+        write!(out, "  ({commit})")?;
+    }
+    if let Some(churn) = entry.churn {
+        write!(out, "  ")?;
+        let mut spec = ColorSpec::new();
+        spec.set_fg(Some(churn_heat_color(churn.heat)));
+        out.set_color(&spec)?;
+        let noun = if churn.commits == 1 {
+            "commit"
+        } else {
+            "commits"
+        };
+        let authors = if churn.authors == 1 {
+            "author"
+        } else {
+            "authors"
+        };
+        write!(
+            out,
+            "[{} {noun} +{} -{}, {} {authors}]",
+            churn.commits, churn.added, churn.removed, churn.authors
+        )?;
+        out.reset()?;
+    }
     if entry.loop_detected {
         write!(out, "  [skipped: circular link]")?;
     }
@@ -1330,7 +3804,174 @@ fn write_plain_entry(
     Ok(())
 }
 
-fn write_csv_entry<W: Write>(out: &mut W, entry: &Entry) -> io::Result<()> {
+/// `git status` と同じく、ステージ済みの桁は緑、未ステージ・未追跡・競合は赤、無視は淡色
+fn git_index_color(status: GitStatus) -> Option<Color> {
+    match status.index {
+        'U' | '?' => Some(Color::Red),
+        '!' | ' ' => None,
+        _ => Some(Color::Green),
+    }
+}
+
+fn git_worktree_color(status: GitStatus) -> Option<Color> {
+    match status.worktree {
+        '!' | ' ' => None,
+        _ => Some(Color::Red),
+    }
+}
+
+/// 衝突中のインデックスのステージ名（`git checkout --ours/--theirs` と同じ呼び方）
+fn stage_label(stage: u8) -> &'static str {
+    match stage {
+        1 => "base",
+        2 => "ours",
+        _ => "theirs",
+    }
+}
+
+/// 同じ種類で最も変更の多いものを赤、その 1/3 未満を緑にする
+fn churn_heat_color(heat: u8) -> Color {
+    match heat {
+        67.. => Color::Red,
+        34..=66 => Color::Yellow,
+        _ => Color::Green,
+    }
+}
+
+fn git_rollup_color(symbol: char) -> Option<Color> {
+    match symbol {
+        'M' | 'T' => Some(Color::Yellow),
+        'A' => Some(Color::Green),
+        'R' => Some(Color::Cyan),
+        'D' | 'U' | '?' => Some(Color::Red),
+        _ => None,
+    }
+}
+
+fn write_git_status_column(
+    out: &mut dyn WriteColor,
+    symbol: char,
+    color: Option<Color>,
+) -> io::Result<()> {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(color).set_dimmed(symbol == '!');
+    out.set_color(&spec)?;
+    write!(out, "{symbol}")?;
+    out.reset()
+}
+
+/// `5.6 GB` のような 1024 単位の概算表記
+pub(crate) fn format_size_human(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+/// CSV / TOON の機能ごとの列。その機能を有効にしたときだけ、基本の列の後ろに足す
+struct TableColumns {
+    /// `--max-entries-per-dir` の要約行の省略件数
+    truncated: bool,
+    rename: bool,
+    log: bool,
+    churn: bool,
+    stage: bool,
+    submodule: bool,
+    lfs: bool,
+}
+
+impl TableColumns {
+    fn from_cli(cli: &Cli) -> Self {
+        Self {
+            truncated: cli.max_entries_per_dir.is_some(),
+            rename: cli.git_rename || cli.rename_threshold.is_some(),
+            log: cli.git_log,
+            churn: matches!(cli.cmd, Some(Cmd::Churn { .. })),
+            stage: cli.source == Source::Index,
+            submodule: wants_git_status(cli),
+            lfs: wants_lfs(cli),
+        }
+    }
+
+    fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.truncated {
+            names.push("truncated");
+        }
+        if self.rename {
+            names.extend(["renamed_from", "similarity"]);
+        }
+        if self.log {
+            names.extend(["last_commit", "last_author", "last_date", "last_subject"]);
+        }
+        if self.churn {
+            names.extend([
+                "churn_commits",
+                "churn_added",
+                "churn_removed",
+                "churn_authors",
+            ]);
+        }
+        if self.stage {
+            names.push("stage");
+        }
+        if self.submodule {
+            names.push("submodule");
+        }
+        if self.lfs {
+            names.extend(["lfs_oid", "lfs_local"]);
+        }
+        names
+    }
+
+    /// [`Self::names`] と同じ順の値
+    fn values(&self, entry: &Entry) -> Vec<Option<String>> {
+        let mut values = Vec::new();
+        if self.truncated {
+            values.push(entry.truncated.map(|n| n.to_string()));
+        }
+        if self.rename {
+            values.push(entry.renamed_from.clone());
+            values.push(entry.similarity.map(|s| s.to_string()));
+        }
+        if self.log {
+            let commit = entry.last_commit.as_ref();
+            values.push(commit.map(|c| c.hash.clone()));
+            values.push(commit.map(|c| c.author.clone()));
+            values.push(commit.map(|c| c.date.clone()));
+            values.push(commit.map(|c| c.subject.clone()));
+        }
+        if self.churn {
+            let churn = entry.churn;
+            values.push(churn.map(|c| c.commits.to_string()));
+            values.push(churn.map(|c| c.added.to_string()));
+            values.push(churn.map(|c| c.removed.to_string()));
+            values.push(churn.map(|c| c.authors.to_string()));
+        }
+        if self.stage {
+            values.push(entry.stage.map(|s| s.to_string()));
+        }
+        if self.submodule {
+            values.push(entry.submodule.map(|s| s.to_string()));
+        }
+        if self.lfs {
+            let lfs = entry.lfs.as_ref();
+            values.push(lfs.map(|l| l.oid.clone()));
+            values.push(lfs.map(|l| l.local.to_string()));
+        }
+        values
+    }
+}
+
+fn write_csv_entry<W: Write>(out: &mut W, entry: &Entry, columns: &TableColumns) -> io::Result<()> {
     csv_escape(out, &entry.name)?;
     write!(out, ",")?;
     csv_escape(out, &entry.path)?;
@@ -1360,6 +4001,14 @@ fn write_csv_entry<W: Write>(out: &mut W, entry: &Entry) -> io::Result<()> {
     write!(out, ",")?;
     if let Some(status) = entry.git_status {
         write!(out, "{status}")?;
+    } else if let Some(changes) = entry.git_changes {
+        write!(out, "{changes}")?;
+    }
+    for value in columns.values(entry) {
+        write!(out, ",")?;
+        if let Some(value) = value {
+            csv_escape(out, &value)?;
+        }
     }
     writeln!(out)?;
     Ok(())
@@ -1386,20 +4035,20 @@ fn csv_escape<W: Write>(out: &mut W, value: &str) -> io::Result<()> {
 // ---------------------------------------------------------------------
 // JSON 出力モード
 // ---------------------------------------------------------------------
-fn run_tree_json(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
+fn run_tree_json(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
     let mut stdout = BufWriter::new(std::io::stdout().lock());
+    let mut budget = OutputBudget::new(cli);
 
-    let mut root_meta = EntryMeta::from_path(root);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -1409,44 +4058,21 @@ fn run_tree_json(
         visited.insert(root.to_path_buf());
     }
 
-    write!(&mut stdout, "[")?;
-    let mut first = true;
-    let mut emit = |entry: &Entry| -> io::Result<()> {
-        if !first {
-            write!(&mut stdout, ",")?;
-        } else {
-            first = false;
-        }
-        writeln!(&mut stdout)?;
-        serde_json::to_writer(&mut stdout, entry)?;
-        Ok(())
-    };
-
-    let root_entry = Entry::from_meta(&root_meta, 0);
-    emit(&root_entry)?;
-
-    if !root_meta.points_to_directory() || matches!(cli.max_depth, Some(1)) {
-        writeln!(&mut stdout)?;
-        write!(&mut stdout, "]\n")?;
-        stdout.flush()?;
-        return Ok(());
-    }
+    // 要素は ",\n" 区切りで、最後に "\n]\n" で閉じる
+    let root_json = serde_json::to_vec(&Entry::from_meta(&root_meta, 0))?;
+    writeln!(&mut stdout, "[")?;
+    stdout.write_all(&root_json)?;
+    budget.charge_fixed(2 + root_json.len());
+    let trailer = budget
+        .worst_trailer(|record, _| 2 + serde_json::to_vec(record).map(|v| v.len()).unwrap_or(0));
+    budget.reserve(3 + trailer);
 
+    let mut stats = TreeStats::new();
     let mut stack: Vec<Frame> = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        root,
-        "",
-        1,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
-        stack.push(frame);
+    if root_meta.points_to_directory() && !root_only(cli) {
+        if let Some(frame) = read_dir_frame(ctx, root, "", 1)? {
+            stack.push(frame);
+        }
     }
 
     while let Some(frame) = stack.last_mut() {
@@ -1470,46 +4096,49 @@ fn run_tree_json(
             root_guard,
         );
 
-        emit(&entry)?;
+        let json = serde_json::to_vec(&entry)?;
+        if !budget.admit(2 + json.len()) {
+            break;
+        }
+        stats.record(&entry);
+        writeln!(&mut stdout, ",")?;
+        stdout.write_all(&json)?;
 
         if descend {
             let child_path = entry_meta.path.clone();
-            if let Some(frame) = read_dir_frame(
-                root,
-                &child_path,
-                &child_prefix,
-                frame.depth + 1,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-            )? {
+            if let Some(frame) = read_dir_frame(ctx, &child_path, &child_prefix, frame.depth + 1)? {
                 stack.push(frame);
             }
         }
     }
 
+    if wants_stats(cli) {
+        writeln!(&mut stdout, ",")?;
+        serde_json::to_writer(&mut stdout, &stats.record_for_output())?;
+    }
+    if let Some(limit) = budget.exhausted() {
+        writeln!(&mut stdout, ",")?;
+        serde_json::to_writer(&mut stdout, &budget.record(limit))?;
+    }
     writeln!(&mut stdout)?;
-    write!(&mut stdout, "]\n")?;
+    writeln!(&mut stdout, "]")?;
     stdout.flush()?;
-    Ok(())
+    Ok(budget.completion())
 }
 
-fn run_tree_toon(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
-    let mut root_meta = EntryMeta::from_path(root);
+fn run_tree_toon(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
+    let mut budget = OutputBudget::new(cli);
+    let columns = TableColumns::from_cli(cli);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -1519,24 +4148,24 @@ fn run_tree_toon(
         visited.insert(root.to_path_buf());
     }
 
+    let rel_root = root_security.as_deref().unwrap_or(root);
+    let mut stats = TreeStats::new();
     let mut entries = Vec::new();
     entries.push(Entry::from_meta(&root_meta, 0));
+    if budget.tracks_bytes() {
+        // 見出しの件数は最大桁数で見積もる
+        budget.charge_fixed(
+            toon_root_line(rel_root).len()
+                + toon_header_line(usize::MAX, &columns).len()
+                + toon_entry_line(rel_root, &entries[0], &columns).len(),
+        );
+        let trailer = budget.worst_trailer(|_, limit| toon_trailer_line(limit).len());
+        budget.reserve(trailer);
+    }
 
-    if root_meta.points_to_directory() && !matches!(cli.max_depth, Some(1)) {
+    if root_meta.points_to_directory() && !root_only(cli) {
         let mut stack: Vec<Frame> = Vec::new();
-        if let Some(frame) = read_dir_frame(
-            root,
-            root,
-            "",
-            1,
-            cli,
-            include_glob,
-            include_prefixes,
-            exclude_glob,
-            filters,
-            git,
-            jobs,
-        )? {
+        if let Some(frame) = read_dir_frame(ctx, root, "", 1)? {
             stack.push(frame);
         }
 
@@ -1561,23 +4190,22 @@ fn run_tree_toon(
                 root_guard,
             );
 
-            entries.push(entry.clone());
+            let cost = if budget.tracks_bytes() {
+                toon_entry_line(rel_root, &entry, &columns).len()
+            } else {
+                0
+            };
+            if !budget.admit(cost) {
+                break;
+            }
+            stats.record(&entry);
+            entries.push(entry);
 
             if descend {
                 let child_path = entry_meta.path.clone();
-                if let Some(frame) = read_dir_frame(
-                    root,
-                    &child_path,
-                    &child_prefix,
-                    frame.depth + 1,
-                    cli,
-                    include_glob,
-                    include_prefixes,
-                    exclude_glob,
-                    filters,
-                    git,
-                    jobs,
-                )? {
+                if let Some(frame) =
+                    read_dir_frame(ctx, &child_path, &child_prefix, frame.depth + 1)?
+                {
                     stack.push(frame);
                 }
             }
@@ -1585,51 +4213,156 @@ fn run_tree_toon(
     }
 
     let mut out = make_encoded_writer(cli);
-    let rel_root = root_security.as_deref().unwrap_or(root);
-    write_toon_dataset(out.as_mut(), rel_root, &entries)?;
+    write_toon_dataset(
+        out.as_mut(),
+        rel_root,
+        &entries,
+        &columns,
+        budget.exhausted(),
+    )?;
+    if wants_stats(cli) {
+        write!(
+            out,
+            "{}",
+            toon_stats_block(rel_root, &stats.record_for_output())
+        )?;
+    }
     out.flush()?;
-    Ok(())
+    Ok(budget.completion())
 }
 
-fn write_toon_dataset(out: &mut dyn Write, root: &Path, entries: &[Entry]) -> io::Result<()> {
-    writeln!(out, "root:{}", root.display())?;
-    writeln!(
-        out,
-        "entries[{}]{{path,depth,kind,size,mtime,perm,symlink_target,loop_detected,error,git_status}}:",
-        entries.len()
-    )?;
-
+fn write_toon_dataset(
+    out: &mut dyn Write,
+    root: &Path,
+    entries: &[Entry],
+    columns: &TableColumns,
+    truncated: Option<BudgetLimit>,
+) -> io::Result<()> {
+    write!(out, "{}", toon_root_line(root))?;
+    if let Some(limit) = truncated {
+        write!(out, "{}", toon_trailer_line(limit))?;
+    }
+    write!(out, "{}", toon_header_line(entries.len(), columns))?;
     for entry in entries {
-        let path = toon_rel_path(root, &entry.path);
-        let depth = entry.depth.to_string();
-        let size = entry.size.map(|s| s.to_string());
-        let kind = match entry.kind {
-            EntryKind::Dir => "dir",
-            EntryKind::File => "file",
-            EntryKind::Symlink => "symlink",
-            EntryKind::Unknown => "unknown",
-        };
-        let loop_flag = if entry.loop_detected { "1" } else { "0" };
-        let git_status = entry.git_status.map(|c| c.to_string());
-
-        let fields = [
-            Some(path.as_str()),
-            Some(depth.as_str()),
-            Some(kind),
-            size.as_deref(),
-            entry.mtime.as_deref(),
-            entry.perm.as_deref(),
-            entry.symlink_target.as_deref(),
-            Some(loop_flag),
-            entry.error.as_deref(),
-            git_status.as_deref(),
-        ];
+        write!(out, "{}", toon_entry_line(root, entry, columns))?;
+    }
+    Ok(())
+}
 
-        let encoded: Vec<String> = fields.iter().map(|f| encode_toon_value(*f)).collect();
-        writeln!(out, "{}", encoded.join(","))?;
+/// `--stats` は表の後ろに `stats:` ブロックとして置く
+fn toon_stats_block(root: &Path, record: &StatsRecord) -> String {
+    let mut block = String::from("stats:\n");
+    for (key, value) in [
+        ("directories", record.directories),
+        ("files", record.files),
+        ("symlinks", record.symlinks),
+        ("bytes", record.bytes),
+        ("loops", record.loops),
+        ("errors", record.errors),
+        ("omitted", record.omitted),
+        ("elapsed_ms", record.elapsed_ms),
+    ] {
+        block.push_str(&format!("  {key}:{value}\n"));
+    }
+    if let Some(deepest) = &record.deepest {
+        let path = toon_rel_path(root, &deepest.path);
+        block.push_str(&format!(
+            "  deepest:{}\n  deepest_depth:{}\n",
+            encode_toon_value(Some(&path)),
+            deepest.depth
+        ));
+    }
+    block.push_str(&format!(
+        "  largest[{}]{{path,size}}:\n",
+        record.largest.len()
+    ));
+    for item in &record.largest {
+        let path = toon_rel_path(root, &item.path);
+        block.push_str(&format!(
+            "    {},{}\n",
+            encode_toon_value(Some(&path)),
+            item.size
+        ));
     }
+    block.push_str(&format!(
+        "  extensions[{}]{{ext,count}}:\n",
+        record.extensions.len()
+    ));
+    for item in &record.extensions {
+        block.push_str(&format!(
+            "    {},{}\n",
+            encode_toon_value(item.ext.as_deref()),
+            item.count
+        ));
+    }
+    block
+}
 
-    Ok(())
+fn toon_root_line(root: &Path) -> String {
+    format!("root:{}\n", root.display())
+}
+
+fn toon_trailer_line(limit: BudgetLimit) -> String {
+    format!("truncated:{}\n", encode_toon_value(Some(&limit.describe())))
+}
+
+fn toon_header_line(count: usize, columns: &TableColumns) -> String {
+    let mut names = vec![
+        "path",
+        "depth",
+        "kind",
+        "size",
+        "mtime",
+        "perm",
+        "symlink_target",
+        "loop_detected",
+        "error",
+        "git_status",
+    ];
+    names.extend(columns.names());
+    format!("entries[{count}]{{{}}}:\n", names.join(","))
+}
+
+fn toon_entry_line(root: &Path, entry: &Entry, columns: &TableColumns) -> String {
+    let path = toon_rel_path(root, &entry.path);
+    let depth = entry.depth.to_string();
+    let size = entry.size.map(|s| s.to_string());
+    let kind = match entry.kind {
+        EntryKind::Dir => "dir",
+        EntryKind::File => "file",
+        EntryKind::Symlink => "symlink",
+        EntryKind::Unknown => "unknown",
+        EntryKind::Truncated => "truncated",
+        EntryKind::Budget => "budget",
+    };
+    let loop_flag = if entry.loop_detected { "1" } else { "0" };
+    // ディレクトリは配下の集計（`M3 A1`）を同じ列に入れる
+    let git_status = entry
+        .git_status
+        .map(|status| status.to_string())
+        .or_else(|| entry.git_changes.map(|changes| changes.to_string()));
+
+    let fields = [
+        Some(path.as_str()),
+        Some(depth.as_str()),
+        Some(kind),
+        size.as_deref(),
+        entry.mtime.as_deref(),
+        entry.perm.as_deref(),
+        entry.symlink_target.as_deref(),
+        Some(loop_flag),
+        entry.error.as_deref(),
+        git_status.as_deref(),
+    ];
+
+    let mut encoded: Vec<String> = fields.iter().map(|f| encode_toon_value(*f)).collect();
+    encoded.extend(
+        columns
+            .values(entry)
+            .iter()
+            .map(|value| encode_toon_value(value.as_deref())),
+    );
+    format!("{}\n", encoded.join(","))
 }
 
 fn toon_rel_path(root: &Path, path: &str) -> String {
@@ -1648,7 +4381,7 @@ fn toon_rel_path(root: &Path, path: &str) -> String {
 
 fn encode_toon_value(value: Option<&str>) -> String {
     match value {
-        Some(v) if v.is_empty() => String::from(""),
+        Some("") => String::from(""),
         Some(v) => {
             let needs_quote = v.chars().any(|c| matches!(c, ',' | '\\' | '\n' | '\r'));
             if needs_quote {
@@ -1659,22 +4392,22 @@ fn encode_toon_value(value: Option<&str>) -> String {
         }
         None => String::from("-"),
     }
-}
-
-fn run_tree_ndjson(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
+}
+
+fn run_tree_ndjson(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
     let mut stdout = BufWriter::new(std::io::stdout().lock());
+    let mut budget = OutputBudget::new(cli);
 
-    let mut root_meta = EntryMeta::from_path(root);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -1684,98 +4417,64 @@ fn run_tree_ndjson(
         visited.insert(root.to_path_buf());
     }
 
-    let root_entry = Entry::from_meta(&root_meta, 0);
-    serde_json::to_writer(&mut stdout, &root_entry)?;
+    let root_json = serde_json::to_vec(&Entry::from_meta(&root_meta, 0))?;
+    stdout.write_all(&root_json)?;
     writeln!(&mut stdout)?;
-
-    if !root_meta.points_to_directory() || matches!(cli.max_depth, Some(1)) {
-        stdout.flush()?;
-        return Ok(());
+    budget.charge_fixed(root_json.len() + 1);
+    let trailer = budget
+        .worst_trailer(|record, _| serde_json::to_vec(record).map(|v| v.len()).unwrap_or(0) + 1);
+    budget.reserve(trailer);
+
+    let mut stats = TreeStats::new();
+    if root_meta.points_to_directory() && !root_only(cli) {
+        walk_entries(ctx, &mut visited, root_guard, |entry| {
+            let json = serde_json::to_vec(entry)?;
+            if !budget.admit(json.len() + 1) {
+                return Ok(false);
+            }
+            stats.record(entry);
+            stdout.write_all(&json)?;
+            writeln!(&mut stdout)?;
+            Ok(true)
+        })?;
     }
 
-    let mut stack: Vec<Frame> = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        root,
-        "",
-        1,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
-        stack.push(frame);
+    if wants_stats(cli) {
+        serde_json::to_writer(&mut stdout, &stats.record_for_output())?;
+        writeln!(&mut stdout)?;
     }
-
-    while let Some(frame) = stack.last_mut() {
-        if frame.idx >= frame.entries.len() {
-            stack.pop();
-            continue;
-        }
-
-        let idx = frame.idx;
-        let is_last = idx + 1 == frame.entries.len();
-        let entry_meta = &mut frame.entries[idx];
-        frame.idx += 1;
-
-        let (entry, descend, child_prefix) = handle_entry_with_guard(
-            entry_meta,
-            &frame.prefix,
-            frame.depth,
-            is_last,
-            cli,
-            &mut visited,
-            root_guard,
-        );
-
-        serde_json::to_writer(&mut stdout, &entry)?;
+    if let Some(limit) = budget.exhausted() {
+        serde_json::to_writer(&mut stdout, &budget.record(limit))?;
         writeln!(&mut stdout)?;
-
-        if descend {
-            let child_path = entry_meta.path.clone();
-            if let Some(frame) = read_dir_frame(
-                root,
-                &child_path,
-                &child_prefix,
-                frame.depth + 1,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-            )? {
-                stack.push(frame);
-            }
-        }
     }
-
     stdout.flush()?;
-    Ok(())
+    Ok(budget.completion())
 }
 
-fn run_tree_csv(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
+fn run_tree_csv(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
     let mut stdout = BufWriter::new(std::io::stdout().lock());
-    writeln!(
-        &mut stdout,
-        "name,path,depth,kind,size,mtime,perm,symlink_target,loop_detected,error,git_status"
-    )?;
+    let mut budget = OutputBudget::new(cli);
+    let columns = TableColumns::from_cli(cli);
+    let mut header = String::from(
+        "name,path,depth,kind,size,mtime,perm,symlink_target,loop_detected,error,git_status",
+    );
+    for name in columns.names() {
+        header.push(',');
+        header.push_str(name);
+    }
+    writeln!(&mut stdout, "{header}")?;
+    budget.charge_fixed(header.len() + 1);
 
-    let mut root_meta = EntryMeta::from_path(root);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -1786,30 +4485,114 @@ fn run_tree_csv(
     }
 
     let root_entry = Entry::from_meta(&root_meta, 0);
-    write_csv_entry(&mut stdout, &root_entry)?;
+    let mut row = Vec::new();
+    write_csv_entry(&mut row, &root_entry, &columns)?;
+    stdout.write_all(&row)?;
+    budget.charge_fixed(row.len());
+    let trailer = budget.worst_trailer(|_, limit| {
+        let mut row = Vec::new();
+        let _ = write_csv_entry(&mut row, &csv_trailer_entry(limit), &columns);
+        row.len()
+    });
+    budget.reserve(trailer);
 
-    if !root_meta.points_to_directory() || matches!(cli.max_depth, Some(1)) {
+    if !root_meta.points_to_directory() || root_only(cli) {
         stdout.flush()?;
-        return Ok(());
+        return Ok(Completion::Complete);
     }
 
-    let mut stack: Vec<Frame> = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        root,
-        "",
-        1,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
-        stack.push(frame);
+    walk_entries(ctx, &mut visited, root_guard, |entry| {
+        row.clear();
+        write_csv_entry(&mut row, entry, &columns)?;
+        if !budget.admit(row.len()) {
+            return Ok(false);
+        }
+        stdout.write_all(&row)?;
+        Ok(true)
+    })?;
+
+    if let Some(limit) = budget.exhausted() {
+        write_csv_entry(&mut stdout, &csv_trailer_entry(limit), &columns)?;
+    }
+    stdout.flush()?;
+    Ok(budget.completion())
+}
+
+/// CSV は列が固定なので、打ち切りは `kind=budget` の行で表す。
+/// `--max-entries-per-dir` の要約（`kind=truncated`）とは種類で見分けられる
+fn csv_trailer_entry(limit: BudgetLimit) -> Entry {
+    Entry {
+        name: format!("… output truncated ({})", limit.describe()),
+        path: String::new(),
+        depth: 0,
+        kind: EntryKind::Budget,
+        size: None,
+        mtime: None,
+        perm: None,
+        symlink_target: None,
+        loop_detected: false,
+        error: None,
+        git_status: None,
+        git_changes: None,
+        renamed_from: None,
+        similarity: None,
+        submodule: None,
+        ghost: false,
+        stage: None,
+        last_commit: None,
+        churn: None,
+        lfs: None,
+        truncated: None,
+        files: None,
+        dirs: None,
+    }
+}
+
+/// NDJSON/CSV 用の走査。`--bfs` なら階層ごと（幅優先）、それ以外は深さ優先で
+/// エントリを `visit` に渡す。`visit` が `false` を返したら打ち切る。
+fn walk_entries(
+    ctx: &WalkContext<'_>,
+    visited: &mut HashSet<PathBuf>,
+    root_guard: Option<&Path>,
+    mut visit: impl FnMut(&Entry) -> Result<bool>,
+) -> Result<()> {
+    let WalkContext { root, cli, .. } = *ctx;
+    let Some(first) = read_dir_frame(ctx, root, "", 1)? else {
+        return Ok(());
+    };
+
+    if cli.bfs {
+        let mut queue = VecDeque::from([first]);
+        while let Some(mut frame) = queue.pop_front() {
+            let len = frame.entries.len();
+            for idx in 0..len {
+                let is_last = idx + 1 == len;
+                let entry_meta = &mut frame.entries[idx];
+                let (entry, descend, child_prefix) = handle_entry_with_guard(
+                    entry_meta,
+                    &frame.prefix,
+                    frame.depth,
+                    is_last,
+                    cli,
+                    visited,
+                    root_guard,
+                );
+                if !visit(&entry)? {
+                    return Ok(());
+                }
+                if descend {
+                    if let Some(child) =
+                        read_dir_frame(ctx, &entry_meta.path, &child_prefix, frame.depth + 1)?
+                    {
+                        queue.push_back(child);
+                    }
+                }
+            }
+        }
+        return Ok(());
     }
 
+    let mut stack = vec![first];
     while let Some(frame) = stack.last_mut() {
         if frame.idx >= frame.entries.len() {
             stack.pop();
@@ -1827,48 +4610,96 @@ fn run_tree_csv(
             frame.depth,
             is_last,
             cli,
-            &mut visited,
+            visited,
             root_guard,
         );
 
-        write_csv_entry(&mut stdout, &entry)?;
+        if !visit(&entry)? {
+            return Ok(());
+        }
 
         if descend {
             let child_path = entry_meta.path.clone();
-            if let Some(frame) = read_dir_frame(
-                root,
-                &child_path,
-                &child_prefix,
-                frame.depth + 1,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-            )? {
+            if let Some(frame) = read_dir_frame(ctx, &child_path, &child_prefix, frame.depth + 1)? {
                 stack.push(frame);
             }
         }
     }
-
-    stdout.flush()?;
     Ok(())
 }
 
-fn run_tree_yaml(
-    root: &Path,
+/// サブコマンドに渡す、走査で見つかったエントリ（ルートは含まない）
+pub(crate) struct TreeItem<'a> {
+    pub path: &'a Path,
+    pub name: &'a str,
+    pub depth: usize,
+    pub is_file: bool,
+    pub is_dir: bool,
+    pub size: Option<u64>,
+    pub mtime: Option<SystemTime>,
+}
+
+/// ツリー表示と同じフィルタ・深さ制限で走査し、各エントリを `visit` に渡す
+pub(crate) fn visit_tree(
     cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
+    root: &Path,
+    mut visit: impl FnMut(&TreeItem<'_>),
 ) -> Result<()> {
-    let mut root_meta = EntryMeta::from_path(root);
+    let matcher = Matcher::from_cli(cli, root)?;
+    let mut filters = Filters::from_cli(cli, root)?;
+    let git = GitTracker::prepare(root, cli)?;
+    filters.use_git(cli, root, &git);
+    let jobs = JobPool::new(cli)?;
+    let ctx = WalkContext {
+        root,
+        cli,
+        matcher: &matcher,
+        filters: &filters,
+        git: &git,
+        jobs: &jobs,
+    };
+
+    let root_meta = git.root_meta(root);
+    let root_security = canonical_root_for_security(root, &root_meta);
+    let root_guard = root_security.as_deref();
+    let mut visited: HashSet<PathBuf> = HashSet::new();
+    visited.insert(root_security.clone().unwrap_or_else(|| root.to_path_buf()));
+
+    if !root_meta.points_to_directory() || root_only(cli) {
+        return Ok(());
+    }
+
+    walk_entries(&ctx, &mut visited, root_guard, |entry| {
+        let path = PathBuf::from(&entry.path);
+        visit(&TreeItem {
+            path: &path,
+            name: &entry.name,
+            depth: entry.depth,
+            is_file: matches!(entry.kind, EntryKind::File),
+            is_dir: matches!(entry.kind, EntryKind::Dir),
+            size: entry.size,
+            mtime: entry
+                .mtime
+                .as_deref()
+                .and_then(|secs| secs.parse().ok())
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+        });
+        Ok(true)
+    })
+}
+
+fn run_tree_yaml(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
+    let mut budget = OutputBudget::new(cli);
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -1880,22 +4711,14 @@ fn run_tree_yaml(
 
     let mut root_entry = Entry::from_meta(&root_meta, 0);
     let mut children = Vec::new();
+    if budget.tracks_bytes() {
+        budget.charge_fixed(yaml_node_cost(&root_entry, 0, true));
+        let trailer = budget.worst_trailer(|_, limit| yaml_trailer(limit).len());
+        budget.reserve(trailer);
+    }
 
-    if root_meta.points_to_directory() && !matches!(cli.max_depth, Some(1)) {
-        children = build_yaml_children(
-            root,
-            &root_meta,
-            1,
-            cli,
-            include_glob,
-            include_prefixes,
-            exclude_glob,
-            filters,
-            git,
-            jobs,
-            &mut visited,
-            root_guard,
-        )?;
+    if root_meta.points_to_directory() && !root_only(cli) {
+        children = build_yaml_children(ctx, &root_meta, 1, &mut visited, root_guard, &mut budget)?;
 
         let mut total = 0u64;
         let mut has_sizes = false;
@@ -1919,53 +4742,63 @@ fn run_tree_yaml(
 
     let mut stdout = BufWriter::new(std::io::stdout().lock());
     write_yaml_node(&mut stdout, &doc, 0, false)?;
+    if let Some(limit) = budget.exhausted() {
+        write!(&mut stdout, "{}", yaml_trailer(limit))?;
+    }
     stdout.flush()?;
-    Ok(())
+    Ok(budget.completion())
+}
+
+/// 打ち切り時はルートに `truncated` を足し、文書終端マーカーで閉じる
+fn yaml_trailer(limit: BudgetLimit) -> String {
+    format!(
+        "truncated: {}\n...\n",
+        serde_json::to_string(&limit.describe()).unwrap()
+    )
+}
+
+/// ノード 1 つ分（子を除く）の YAML のバイト数。ディレクトリは集計後のサイズが
+/// 未確定なので最大桁で見積もり、`children:` 行も含める
+fn yaml_node_cost(entry: &Entry, depth: usize, is_dir: bool) -> usize {
+    let mut entry = entry.clone();
+    if is_dir {
+        entry.size = Some(u64::MAX);
+    }
+    let node = YamlNode {
+        entry,
+        children: Vec::new(),
+    };
+    let (indent, with_dash) = if depth == 0 {
+        (0, false)
+    } else {
+        (4 * depth - 2, true)
+    };
+    let mut buf = Vec::new();
+    let _ = write_yaml_node(&mut buf, &node, indent, with_dash);
+    let children_line = if is_dir {
+        4 * depth + "children:\n".len()
+    } else {
+        0
+    };
+    buf.len() + children_line
 }
 
 fn build_yaml_children(
-    root: &Path,
+    ctx: &WalkContext<'_>,
     parent_meta: &EntryMeta,
     depth: usize,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
     visited: &mut HashSet<PathBuf>,
     root_guard: Option<&Path>,
+    budget: &mut OutputBudget,
 ) -> Result<Vec<YamlNode>> {
     let mut nodes = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        &parent_meta.path,
-        "",
-        depth,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
+    if let Some(frame) = read_dir_frame(ctx, &parent_meta.path, "", depth)? {
         for mut meta in frame.entries.into_iter() {
-            let node = build_yaml_node(
-                &mut meta,
-                frame.depth,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-                visited,
-                root_guard,
-                root,
-            )?;
+            let Some(node) =
+                build_yaml_node(ctx, &mut meta, frame.depth, visited, root_guard, budget)?
+            else {
+                break;
+            };
             nodes.push(node);
         }
     }
@@ -1974,39 +4807,30 @@ fn build_yaml_children(
 }
 
 fn build_yaml_node(
+    ctx: &WalkContext<'_>,
     meta: &mut EntryMeta,
     depth: usize,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
     visited: &mut HashSet<PathBuf>,
     root_guard: Option<&Path>,
-    root: &Path,
-) -> Result<YamlNode> {
+    budget: &mut OutputBudget,
+) -> Result<Option<YamlNode>> {
+    let cli = ctx.cli;
     let (mut entry, descend, _child_prefix) =
         handle_entry_with_guard(meta, "", depth, true, cli, visited, root_guard);
 
+    let estimate = if budget.tracks_bytes() {
+        yaml_node_cost(&entry, depth, descend)
+    } else {
+        0
+    };
+    if !budget.admit(estimate) {
+        return Ok(None);
+    }
+
     let mut children = Vec::new();
     if descend {
         entry.size = None;
-        children = build_yaml_children(
-            root,
-            meta,
-            depth + 1,
-            cli,
-            include_glob,
-            include_prefixes,
-            exclude_glob,
-            filters,
-            git,
-            jobs,
-            visited,
-            root_guard,
-        )?;
+        children = build_yaml_children(ctx, meta, depth + 1, visited, root_guard, budget)?;
         let mut total = 0u64;
         let mut has_sizes = false;
         for child in &children {
@@ -2018,74 +4842,90 @@ fn build_yaml_node(
         if has_sizes {
             entry.size = Some(total);
         }
+        if budget.tracks_bytes() {
+            let mut actual = yaml_node_cost(&entry, depth, false);
+            if !children.is_empty() {
+                actual += 4 * depth + "children:\n".len();
+            }
+            budget.refund(estimate.saturating_sub(actual));
+        }
     }
 
-    Ok(YamlNode { entry, children })
+    Ok(Some(YamlNode { entry, children }))
 }
 
-fn run_tree_html(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<()> {
-    let entries = collect_entries_flat(
-        root,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )?;
+fn run_tree_html(ctx: &WalkContext<'_>) -> Result<Completion> {
+    let mut budget = OutputBudget::new(ctx.cli);
+    if budget.tracks_bytes() {
+        // 枠の HTML は固定。データ部はエントリごとに JSON 1 件 + 区切りの "," を数える
+        let mut frame = Vec::new();
+        write_html(&mut frame, "[]", None)?;
+        budget.charge_fixed(frame.len());
+        let trailer = budget.worst_trailer(|_, limit| html_trailer(limit).len());
+        budget.reserve(trailer);
+    }
+    let entries = collect_entries_flat(ctx, &mut budget)?;
     let json = serde_json::to_string(&entries)?;
     let escaped = escape_script_data(&json);
 
     let mut stdout = BufWriter::new(std::io::stdout().lock());
-    writeln!(&mut stdout, "<!DOCTYPE html>")?;
-    writeln!(&mut stdout, "<html lang=\"en\">")?;
-    writeln!(&mut stdout, "<head>")?;
-    writeln!(&mut stdout, "  <meta charset=\"utf-8\">")?;
-    writeln!(&mut stdout, "  <title>printree</title>")?;
+    write_html(&mut stdout, &escaped, budget.exhausted())?;
+    stdout.flush()?;
+    Ok(budget.completion())
+}
+
+fn html_trailer(limit: BudgetLimit) -> String {
+    format!(
+        "<p class=\"truncated\">… output truncated ({})</p>\n",
+        limit.describe()
+    )
+}
+
+fn write_html<W: Write>(
+    stdout: &mut W,
+    escaped: &str,
+    truncated: Option<BudgetLimit>,
+) -> io::Result<()> {
+    writeln!(stdout, "<!DOCTYPE html>")?;
+    writeln!(stdout, "<html lang=\"en\">")?;
+    writeln!(stdout, "<head>")?;
+    writeln!(stdout, "  <meta charset=\"utf-8\">")?;
+    writeln!(stdout, "  <title>printree</title>")?;
     writeln!(
-        &mut stdout,
+        stdout,
         "  <style>body {{ font-family: monospace; white-space: pre; margin: 2rem; }}</style>"
     )?;
-    writeln!(&mut stdout, "</head>")?;
-    writeln!(&mut stdout, "<body>")?;
+    writeln!(stdout, "</head>")?;
+    writeln!(stdout, "<body>")?;
     writeln!(
-        &mut stdout,
+        stdout,
         "<script type=\"application/json\" id=\"tree-data\">{}</script>",
         escaped
     )?;
-    writeln!(&mut stdout, "<pre id=\"tree-output\"></pre>")?;
+    writeln!(stdout, "<pre id=\"tree-output\"></pre>")?;
+    if let Some(limit) = truncated {
+        write!(stdout, "{}", html_trailer(limit))?;
+    }
     writeln!(
-        &mut stdout,
+        stdout,
         "<script>const data=JSON.parse(document.getElementById('tree-data').textContent);\nconst lines=data.map(e=>`${{'    '.repeat(e.depth)}}${{e.name}}`);\ndocument.getElementById('tree-output').textContent=lines.join('\\n');</script>"
     )?;
-    writeln!(&mut stdout, "</body>")?;
-    writeln!(&mut stdout, "</html>")?;
-    stdout.flush()?;
+    writeln!(stdout, "</body>")?;
+    writeln!(stdout, "</html>")?;
     Ok(())
 }
 
-fn collect_entries_flat(
-    root: &Path,
-    cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
-    git: &GitTracker,
-    jobs: &JobPool,
-) -> Result<Vec<Entry>> {
-    let mut root_meta = EntryMeta::from_path(root);
+fn collect_entries_flat(ctx: &WalkContext<'_>, budget: &mut OutputBudget) -> Result<Vec<Entry>> {
+    let WalkContext {
+        root,
+        cli,
+        filters,
+        git,
+        ..
+    } = *ctx;
+    let mut root_meta = git.root_meta(root);
     git.apply(&mut root_meta);
+    filters.annotate(&mut root_meta);
     let root_security = canonical_root_for_security(root, &root_meta);
     let root_guard = root_security.as_deref();
     let mut visited: HashSet<PathBuf> = HashSet::new();
@@ -2097,25 +4937,16 @@ fn collect_entries_flat(
 
     let mut entries = Vec::new();
     entries.push(Entry::from_meta(&root_meta, 0));
+    if budget.tracks_bytes() {
+        budget.charge_fixed(serde_json::to_vec(&entries[0])?.len());
+    }
 
-    if !root_meta.points_to_directory() || matches!(cli.max_depth, Some(1)) {
+    if !root_meta.points_to_directory() || root_only(cli) {
         return Ok(entries);
     }
 
     let mut stack: Vec<Frame> = Vec::new();
-    if let Some(frame) = read_dir_frame(
-        root,
-        root,
-        "",
-        1,
-        cli,
-        include_glob,
-        include_prefixes,
-        exclude_glob,
-        filters,
-        git,
-        jobs,
-    )? {
+    if let Some(frame) = read_dir_frame(ctx, root, "", 1)? {
         stack.push(frame);
     }
 
@@ -2140,21 +4971,18 @@ fn collect_entries_flat(
             root_guard,
         );
 
+        let cost = if budget.tracks_bytes() {
+            escape_script_data(&serde_json::to_string(&entry)?).len() + 1
+        } else {
+            0
+        };
+        if !budget.admit(cost) {
+            break;
+        }
+
         if descend {
             let child_path = entry_meta.path.clone();
-            if let Some(frame) = read_dir_frame(
-                root,
-                &child_path,
-                &child_prefix,
-                frame.depth + 1,
-                cli,
-                include_glob,
-                include_prefixes,
-                exclude_glob,
-                filters,
-                git,
-                jobs,
-            )? {
+            if let Some(frame) = read_dir_frame(ctx, &child_path, &child_prefix, frame.depth + 1)? {
                 stack.push(frame);
             }
         }
@@ -2205,6 +5033,9 @@ fn write_yaml_fields<W: Write>(out: &mut W, indent: usize, node: &YamlNode) -> i
     if let Some(size) = node.entry.size {
         writeln!(out, "{}size: {}", indent_str, size)?;
     }
+    if let Some(count) = node.entry.truncated {
+        writeln!(out, "{}truncated: {}", indent_str, count)?;
+    }
     if let Some(mtime) = &node.entry.mtime {
         yaml_write_string(out, indent, "mtime", mtime)?;
     }
@@ -2223,7 +5054,58 @@ fn write_yaml_fields<W: Write>(out: &mut W, indent: usize, node: &YamlNode) -> i
         yaml_write_string(out, indent, "error", err)?;
     }
     if let Some(status) = node.entry.git_status {
-        writeln!(out, "{}git_status: {}", indent_str, status)?;
+        yaml_write_string(out, indent, "git_status", &status.to_string())?;
+    }
+    if node.entry.ghost {
+        writeln!(out, "{}ghost: true", indent_str)?;
+    }
+    if let Some(churn) = node.entry.churn {
+        writeln!(out, "{}churn:", indent_str)?;
+        writeln!(out, "{}  commits: {}", indent_str, churn.commits)?;
+        writeln!(out, "{}  added: {}", indent_str, churn.added)?;
+        writeln!(out, "{}  removed: {}", indent_str, churn.removed)?;
+        writeln!(out, "{}  authors: {}", indent_str, churn.authors)?;
+    }
+    if let Some(commit) = &node.entry.last_commit {
+        writeln!(out, "{}last_commit:", indent_str)?;
+        let inner = indent + 2;
+        yaml_write_string(out, inner, "hash", &commit.hash)?;
+        yaml_write_string(out, inner, "author", &commit.author)?;
+        yaml_write_string(out, inner, "date", &commit.date)?;
+        yaml_write_string(out, inner, "relative", &commit.relative)?;
+        yaml_write_string(out, inner, "subject", &commit.subject)?;
+    }
+    if let Some(from) = &node.entry.renamed_from {
+        yaml_write_string(out, indent, "renamed_from", from)?;
+    }
+    if let Some(similarity) = node.entry.similarity {
+        writeln!(out, "{}similarity: {}", indent_str, similarity)?;
+    }
+    if let Some(stage) = node.entry.stage {
+        writeln!(out, "{}stage: {}", indent_str, stage)?;
+    }
+    if let Some(submodule) = node.entry.submodule {
+        yaml_write_string(out, indent, "submodule", &submodule.to_string())?;
+    }
+    if let Some(lfs) = &node.entry.lfs {
+        writeln!(out, "{}lfs:", indent_str)?;
+        let inner = indent + 2;
+        yaml_write_string(out, inner, "oid", &lfs.oid)?;
+        writeln!(out, "{}size: {}", " ".repeat(inner), lfs.size)?;
+        writeln!(out, "{}local: {}", " ".repeat(inner), lfs.local)?;
+    }
+    if let Some(changes) = node.entry.git_changes {
+        writeln!(out, "{}git_changes:", indent_str)?;
+        for (symbol, count) in changes.entries() {
+            let key = serde_json::to_string(&symbol.to_string()).unwrap();
+            writeln!(out, "{}  {}: {}", indent_str, key, count)?;
+        }
+    }
+    if let Some(files) = node.entry.files {
+        writeln!(out, "{}files: {}", indent_str, files)?;
+    }
+    if let Some(dirs) = node.entry.dirs {
+        writeln!(out, "{}dirs: {}", indent_str, dirs)?;
     }
     if !node.children.is_empty() {
         writeln!(out, "{}children:", indent_str)?;
@@ -2251,35 +5133,34 @@ fn entry_kind_label(kind: EntryKind) -> &'static str {
         EntryKind::Dir => "dir",
         EntryKind::Symlink => "symlink",
         EntryKind::Unknown => "unknown",
+        EntryKind::Truncated => "truncated",
+        EntryKind::Budget => "budget",
     }
 }
 
 // ---------------------------------------------------------------------
 // ヘルパー関数
 // ---------------------------------------------------------------------
-fn read_dir_frame(
-    root: &Path,
+/// ディレクトリを読み、`--git-ghosts` の削除済みエントリも加える。読めなければ空
+fn read_fs_metas(
     path: &Path,
-    prefix: &str,
-    depth: usize,
     cli: &Cli,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    exclude_glob: &Option<PatternList>,
-    filters: &Filters,
+    matcher: &Matcher,
     git: &GitTracker,
     jobs: &JobPool,
-) -> Result<Option<Frame>> {
+) -> Vec<EntryMeta> {
     let rd = match fs::read_dir(path) {
-        Ok(r) => r,
+        Ok(r) => Some(r),
+        // 丸ごと削除されたディレクトリは削除済みの子だけで組み立てる
+        Err(_) if git.has_ghosts_in(path) && fs::symlink_metadata(path).is_err() => None,
         Err(e) => {
             eprintln!("{} [permission denied: {}]", path.display(), e);
-            return Ok(None);
+            return Vec::new();
         }
     };
 
     let mut seeds: Vec<EntrySeed> = Vec::new();
-    for e in rd {
+    for e in rd.into_iter().flatten() {
         match e {
             Ok(de) => {
                 let file_name = de.file_name();
@@ -2299,39 +5180,11 @@ fn read_dir_frame(
                 }
 
                 let fullp = de.path();
-                let target = match cli.match_mode {
-                    MatchMode::Name => fullp.file_name().map(PathBuf::from).unwrap_or_default(),
-                    MatchMode::Path => fullp.strip_prefix(root).unwrap_or(&fullp).to_path_buf(),
-                };
-
-                let excluded = exclude_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(false);
-                if excluded {
-                    continue;
-                }
-
-                let include_ok = include_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(true);
-
                 let is_dir = file_type_hint
                     .as_ref()
                     .map(|ft| ft.is_dir())
                     .unwrap_or_else(|| fullp.is_dir());
-
-                let allow_dir = is_dir
-                    && include_dir_allowed(
-                        root,
-                        &fullp,
-                        include_glob,
-                        include_prefixes,
-                        cli.match_mode,
-                    );
-
-                if !include_ok && !allow_dir {
+                if !matcher.allows(&fullp, is_dir) {
                     continue;
                 }
 
@@ -2348,33 +5201,100 @@ fn read_dir_frame(
         }
     }
 
-    if seeds.is_empty() {
-        return Ok(None);
-    }
-
-    let metas = build_entry_metas(seeds, jobs);
+    let ghosts = allow_git_entries(git.ghosts_in(path), cli, matcher);
+    let mut metas = build_entry_metas(seeds, jobs);
+    metas.extend(ghosts);
+    metas
+}
+
+/// ディレクトリの子を読み、フィルタと git の注釈をかける（先読みによる除外はしない）
+fn read_filtered_metas(ctx: &WalkContext<'_>, path: &Path) -> Vec<EntryMeta> {
+    let WalkContext {
+        cli,
+        matcher,
+        filters,
+        git,
+        jobs,
+        ..
+    } = *ctx;
+    let metas = match &git.snapshot {
+        Some(snapshot) => match snapshot.children(path) {
+            Some(children) => allow_git_entries(children, cli, matcher),
+            None => return Vec::new(),
+        },
+        None => read_fs_metas(path, cli, matcher, git, jobs),
+    };
+
     let mut entries = Vec::new();
     for mut meta in metas {
+        git.detect_lfs(&mut meta);
         if !filters.allows(&meta) {
             continue;
         }
         git.apply(&mut meta);
+        if !git.keeps(&meta, cli) {
+            continue;
+        }
+        filters.annotate(&mut meta);
         entries.push(meta);
     }
+    entries
+}
+
+/// 一致する子孫があるときだけ残すディレクトリか。`--prune` ではすべて、それ以外では
+/// 権限条件を満たさないものが対象。シンボリックリンクは辿らないので葉として残す
+fn needs_lookahead(filters: &Filters, meta: &EntryMeta) -> bool {
+    meta.is_directory() && !meta.is_symlink && (filters.prune || !filters.perm_allows(meta))
+}
+
+fn read_dir_frame(
+    ctx: &WalkContext<'_>,
+    path: &Path,
+    prefix: &str,
+    depth: usize,
+) -> Result<Option<Frame>> {
+    let WalkContext {
+        cli, filters, git, ..
+    } = *ctx;
+    // 先読みで絞り込み済みなら、ディレクトリを読み直さずにそれを使う
+    let mut entries = match filters.lookahead.take(path) {
+        Some(entries) => entries,
+        None => {
+            let mut kept = Vec::new();
+            for meta in read_filtered_metas(ctx, path) {
+                // 一致する子孫のないディレクトリは落とす
+                if needs_lookahead(filters, &meta)
+                    && !dir_has_visible_entries(ctx, &meta.path, depth + 1)?
+                {
+                    continue;
+                }
+                kept.push(meta);
+            }
+            kept
+        }
+    };
+
+    if let Some(check) = &filters.normalization {
+        check.report(path, &entries);
+    }
 
     if entries.is_empty() {
         return Ok(None);
     }
 
-    if matches!(cli.sort, SortMode::Name) {
-        entries.sort_by(|a, b| a.sort_key().cmp(b.sort_key()));
-    }
-    if cli.dirs_first {
-        entries.sort_by(|a, b| {
-            let ad = a.points_to_directory();
-            let bd = b.points_to_directory();
-            bd.cmp(&ad).then_with(|| a.sort_key().cmp(b.sort_key()))
-        });
+    sort_entries(&mut entries, cli, filters.dir_sizes.as_ref());
+
+    if let Some(limit) = cli.max_entries_per_dir {
+        if entries.len() > limit {
+            let omitted = entries.split_off(limit);
+            entries.push(EntryMeta::truncated_summary(
+                path,
+                &omitted,
+                filters.dir_sizes.as_ref(),
+                git,
+                cli.hidden,
+            ));
+        }
     }
 
     Ok(Some(Frame {
@@ -2385,6 +5305,260 @@ fn read_dir_frame(
     }))
 }
 
+/// `--sort` / `--reverse` / `--dirs-first` に従って兄弟エントリを並べ替える。
+/// 同順位は名前順で決め、`--dirs-first` は並び順を保ったままディレクトリを前に寄せる。
+fn sort_entries(entries: &mut [EntryMeta], cli: &Cli, dir_sizes: Option<&DirSizeIndex>) {
+    match cli.sort {
+        SortMode::None => {}
+        SortMode::Name => match cli.collation {
+            None => entries.sort_by(|a, b| a.sort_key().cmp(b.sort_key())),
+            Some(collation) => entries.sort_by_cached_key(|meta| {
+                (collation_key(&meta.name, collation), meta.name.clone())
+            }),
+        },
+        SortMode::Natural => entries.sort_by(|a, b| {
+            natural_cmp(&name_text(a, cli.collation), &name_text(b, cli.collation))
+                .then_with(|| compare_names(a, b, cli.collation))
+        }),
+        // 大きい順・新しい順を既定にし、`--reverse` で昇順にする
+        SortMode::Size => entries.sort_by(|a, b| {
+            sort_size(b, dir_sizes)
+                .cmp(&sort_size(a, dir_sizes))
+                .then_with(|| compare_names(a, b, cli.collation))
+        }),
+        SortMode::Mtime => entries.sort_by(|a, b| {
+            b.mtime
+                .cmp(&a.mtime)
+                .then_with(|| compare_names(a, b, cli.collation))
+        }),
+        SortMode::Ext => entries.sort_by(|a, b| {
+            sort_extension(a)
+                .cmp(&sort_extension(b))
+                .then_with(|| compare_names(a, b, cli.collation))
+        }),
+        SortMode::Kind => entries.sort_by(|a, b| {
+            kind_rank(a)
+                .cmp(&kind_rank(b))
+                .then_with(|| compare_names(a, b, cli.collation))
+        }),
+        SortMode::GitStatus => entries.sort_by(|a, b| {
+            let ap = git_sort_priority(a);
+            let bp = git_sort_priority(b);
+            bp.cmp(&ap).then_with(|| compare_names(a, b, cli.collation))
+        }),
+    }
+    if cli.reverse {
+        entries.reverse();
+    }
+    if cli.dirs_first {
+        entries.sort_by_key(|meta| !meta.points_to_directory());
+    }
+}
+
+/// ディレクトリは配下の集計で、それ以外は自身の状態で比べる
+fn git_sort_priority(meta: &EntryMeta) -> u8 {
+    let own = meta.git_status.map(GitStatus::priority).unwrap_or(0);
+    let below = meta.git_changes.map(|c| c.priority()).unwrap_or(0);
+    own.max(below)
+}
+
+/// 同順位の決定に使う名前比較。`--collation` 指定時は正規化したキーを先に比べる
+fn compare_names(a: &EntryMeta, b: &EntryMeta, collation: Option<Collation>) -> Ordering {
+    match collation {
+        None => a.sort_key().cmp(b.sort_key()),
+        Some(collation) => collation_key(&a.name, collation)
+            .cmp(&collation_key(&b.name, collation))
+            .then_with(|| a.sort_key().cmp(b.sort_key())),
+    }
+}
+
+fn name_text(meta: &EntryMeta, collation: Option<Collation>) -> String {
+    match collation {
+        None => meta.name.to_string_lossy().into_owned(),
+        Some(collation) => collation_key(&meta.name, collation),
+    }
+}
+
+/// 照合順序ごとの比較キー。
+/// - `unicode`: NFKC（全角英数を半角へ寄せる）＋大文字小文字の畳み込み
+/// - `ascii-ci`: NFC ＋ ASCII のみ小文字化
+/// - `ja`: `unicode` に加えてカタカナをひらがなへ寄せる（半角カナは NFKC で全角になる）
+fn collation_key(name: &OsString, collation: Collation) -> String {
+    let name = name.to_string_lossy();
+    match collation {
+        Collation::Unicode => name.nfkc().collect::<String>().to_lowercase(),
+        Collation::AsciiCi => name.nfc().collect::<String>().to_ascii_lowercase(),
+        Collation::Ja => name
+            .nfkc()
+            .collect::<String>()
+            .to_lowercase()
+            .chars()
+            .map(katakana_to_hiragana)
+            .collect(),
+    }
+}
+
+fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        '\u{30A1}'..='\u{30F6}' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+/// ディレクトリは再帰合計、ファイルは自身のサイズ。不明なものは最小扱い
+fn sort_size(meta: &EntryMeta, dir_sizes: Option<&DirSizeIndex>) -> Option<u64> {
+    if meta.is_directory() && !meta.is_symlink {
+        if let Some(total) = dir_sizes.and_then(|index| index.total_for(&meta.path)) {
+            return Some(total);
+        }
+    }
+    meta.size
+}
+
+/// 拡張子（小文字化）。拡張子の無いエントリが先頭に来る
+fn sort_extension(meta: &EntryMeta) -> String {
+    Path::new(&meta.name)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+fn kind_rank(meta: &EntryMeta) -> u8 {
+    if meta.is_symlink {
+        2
+    } else if meta.is_directory() {
+        0
+    } else if meta.file_type.map(|ft| ft.is_file()).unwrap_or(false) {
+        1
+    } else {
+        3
+    }
+}
+
+/// 数字の並びを数値として比べる自然順（`file2` < `file10`）。
+/// 数値が等しい場合は桁数の少ない方（先頭ゼロの少ない方）を前にする。
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut ai = a.chars().peekable();
+    let mut bi = b.chars().peekable();
+    loop {
+        match (ai.peek().copied(), bi.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(ac), Some(bc)) if ac.is_ascii_digit() && bc.is_ascii_digit() => {
+                let an = take_digits(&mut ai);
+                let bn = take_digits(&mut bi);
+                let at = an.trim_start_matches('0');
+                let bt = bn.trim_start_matches('0');
+                let ord = at
+                    .len()
+                    .cmp(&bt.len())
+                    .then_with(|| at.cmp(bt))
+                    .then_with(|| an.len().cmp(&bn.len()));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+            }
+            (Some(ac), Some(bc)) => {
+                let ord = ac
+                    .to_lowercase()
+                    .cmp(bc.to_lowercase())
+                    .then_with(|| ac.cmp(&bc));
+                if ord != Ordering::Equal {
+                    return ord;
+                }
+                ai.next();
+                bi.next();
+            }
+        }
+    }
+}
+
+fn take_digits(chars: &mut std::iter::Peekable<std::str::Chars<'_>>) -> String {
+    let mut digits = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_digit()) {
+        digits.push(c);
+    }
+    digits
+}
+
+/// git 由来のエントリ（削除済み・`--rev`）にも実在のエントリと同じ名前・種類・パターンの絞り込みをかける
+fn allow_git_entries(metas: Vec<EntryMeta>, cli: &Cli, matcher: &Matcher) -> Vec<EntryMeta> {
+    metas
+        .into_iter()
+        .filter(|meta| cli.hidden || !is_hidden(&meta.name))
+        .filter(|meta| {
+            let is_dir = matches!(meta.git_kind, Some(EntryKind::Dir));
+            let is_symlink = matches!(meta.git_kind, Some(EntryKind::Symlink));
+            allow_kind(is_dir, is_symlink, &cli.types)
+        })
+        .filter(|meta| matcher.allows(&meta.path, meta.is_directory()))
+        .collect()
+}
+
+/// `--prune` 用の先読み。`--max-depth` より深い階層も調べ、一致する子孫があれば残す。
+/// `depth` は `path` 直下のエントリの深さ。表示されるディレクトリの絞り込み結果は
+/// [`LookaheadCache`] に残し、走査側の [`read_dir_frame`] がそのまま使う
+fn dir_has_visible_entries(ctx: &WalkContext<'_>, path: &Path, depth: usize) -> Result<bool> {
+    /// 先読み中のディレクトリ。`dir` は親の `kept` に戻すための自身のエントリ
+    struct Pending {
+        dir: Option<EntryMeta>,
+        path: PathBuf,
+        depth: usize,
+        rest: std::vec::IntoIter<EntryMeta>,
+        kept: Vec<EntryMeta>,
+    }
+
+    let WalkContext { cli, filters, .. } = *ctx;
+    let cache = &filters.lookahead;
+    if cache.contains(path) {
+        return Ok(true);
+    }
+    let limit = print_depth_limit(cli);
+    let mut stack = vec![Pending {
+        dir: None,
+        path: path.to_path_buf(),
+        depth,
+        rest: read_filtered_metas(ctx, path).into_iter(),
+        kept: Vec::new(),
+    }];
+    // 深い木でもネイティブのスタックを使わないよう、明示的なスタックで帰りがけ順に辿る
+    while let Some(top) = stack.last_mut() {
+        if let Some(meta) = top.rest.next() {
+            if !needs_lookahead(filters, &meta) || cache.contains(&meta.path) {
+                top.kept.push(meta);
+                continue;
+            }
+            let depth = top.depth + 1;
+            let rest = read_filtered_metas(ctx, &meta.path).into_iter();
+            stack.push(Pending {
+                path: meta.path.clone(),
+                dir: Some(meta),
+                depth,
+                rest,
+                kept: Vec::new(),
+            });
+            continue;
+        }
+
+        let done = stack.pop().unwrap();
+        let visible = !done.kept.is_empty();
+        // 走査側が降りる深さのものだけ、絞り込んだエントリを取っておく
+        if visible && limit.is_none_or(|max| done.depth <= max) {
+            cache.record(done.path, done.kept);
+        }
+        match (done.dir, stack.last_mut()) {
+            (Some(dir), Some(parent)) => {
+                if visible {
+                    parent.kept.push(dir);
+                }
+            }
+            _ => return Ok(visible),
+        }
+    }
+    Ok(false)
+}
+
 fn build_entry_metas(seeds: Vec<EntrySeed>, jobs: &JobPool) -> Vec<EntryMeta> {
     if seeds.is_empty() {
         return Vec::new();
@@ -2395,7 +5569,7 @@ fn build_entry_metas(seeds: Vec<EntrySeed>, jobs: &JobPool) -> Vec<EntryMeta> {
     }
 
     let workers = jobs.workers().min(seeds.len());
-    let chunk = (seeds.len() + workers - 1) / workers;
+    let chunk = seeds.len().div_ceil(workers);
     let mut results: Vec<EntryMeta> = Vec::with_capacity(seeds.len());
 
     thread::scope(|scope| {
@@ -2417,3 +5591,1150 @@ fn build_entry_metas(seeds: Vec<EntrySeed>, jobs: &JobPool) -> Vec<EntryMeta> {
 
     results
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    const DAY: u64 = 60 * 60 * 24;
+
+    /// `run_tree_at` と同じ準備をして、ルートを含む全エントリを集める
+    fn collect_entries(args: &[&str]) -> Vec<Entry> {
+        let cli = Cli::parse_from(std::iter::once("printree").chain(args.iter().copied()));
+        let root = cli.path.clone().unwrap();
+        let matcher = Matcher::from_cli(&cli, &root).unwrap();
+        let mut filters = Filters::from_cli(&cli, &root).unwrap();
+        let git = GitTracker::prepare(&root, &cli).unwrap();
+        filters.use_git(&cli, &root, &git);
+        let jobs = JobPool::new(&cli).unwrap();
+        if cli.summary_depth.is_some() {
+            let summary = SummaryIndex::build(&WalkContext {
+                root: &root,
+                cli: &cli,
+                matcher: &matcher,
+                filters: &filters,
+                git: &git,
+                jobs: &jobs,
+            })
+            .unwrap();
+            filters.summary = Some(summary);
+        }
+        let ctx = WalkContext {
+            root: &root,
+            cli: &cli,
+            matcher: &matcher,
+            filters: &filters,
+            git: &git,
+            jobs: &jobs,
+        };
+        collect_entries_flat(&ctx, &mut OutputBudget::new(&cli)).unwrap()
+    }
+
+    fn collect_names(args: &[&str]) -> Vec<String> {
+        collect_entries(args)
+            .into_iter()
+            .skip(1)
+            .map(|entry| entry.name)
+            .collect()
+    }
+
+    /// ワークツリーをすべてステージし、HEAD の上にコミットする
+    fn commit_all(repo: &Repository, message: &str) -> git2::Oid {
+        let mut index = repo.index().unwrap();
+        index
+            .add_all(["*"], git2::IndexAddOption::DEFAULT, None)
+            .unwrap();
+        index.write().unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let sig = git2::Signature::now("t", "t@example.com").unwrap();
+        let parent = repo.head().ok().and_then(|head| head.peel_to_commit().ok());
+        let parents: Vec<_> = parent.iter().collect();
+        repo.commit(Some("HEAD"), &sig, &sig, message, &tree, &parents)
+            .unwrap()
+    }
+
+    #[test]
+    fn time_filter_relative_forms_compare_by_age() {
+        let now = UNIX_EPOCH + Duration::from_secs(100 * DAY);
+        let recent = Some(now - Duration::from_secs(DAY));
+        let stale = Some(now - Duration::from_secs(40 * DAY));
+
+        let within = parse_time_filter("3d", "--filter-mtime", now).unwrap();
+        assert!(within.allows(recent));
+        assert!(!within.allows(stale));
+
+        let older = parse_time_filter(">30d", "--filter-mtime", now).unwrap();
+        assert!(!older.allows(recent));
+        assert!(older.allows(stale));
+        assert!(!older.allows(None));
+
+        // 相対指定の範囲は両端を含む
+        let range = parse_time_filter("30d..7d", "--filter-mtime", now).unwrap();
+        let ago = |days: u64| Some(now - Duration::from_secs(days * DAY));
+        assert!(range.allows(ago(30)));
+        assert!(range.allows(ago(7)));
+        assert!(!range.allows(Some(now - Duration::from_secs(7 * DAY - 1))));
+        assert!(!range.allows(Some(now - Duration::from_secs(30 * DAY + 1))));
+    }
+
+    #[test]
+    fn time_filter_absolute_dates_and_ranges() {
+        let now = SystemTime::now();
+        let day = |s: &str| parse_absolute_time(s).unwrap();
+        let jan1 = day("2025-01-01");
+        let feb15 = day("2025-02-15");
+        let apr1 = day("2025-04-01");
+
+        let after = parse_time_filter(">=2025-02-01", "--filter-mtime", now).unwrap();
+        assert!(!after.allows(Some(jan1.start)));
+        assert!(after.allows(Some(feb15.start)));
+
+        let range = parse_time_filter("2025-01-01..2025-03-31", "--filter-mtime", now).unwrap();
+        assert!(range.allows(Some(jan1.start)));
+        assert!(range.allows(Some(feb15.start)));
+        assert!(!range.allows(Some(apr1.start)));
+
+        let single = parse_time_filter("2025-01-01", "--filter-mtime", now).unwrap();
+        assert!(single.allows(Some(jan1.start + Duration::from_secs(DAY - 1))));
+        assert!(!single.allows(Some(jan1.end)));
+    }
+
+    #[test]
+    fn time_filter_rejects_bad_specs() {
+        let now = SystemTime::now();
+        assert!(parse_time_filter("", "--filter-mtime", now).is_err());
+        assert!(parse_time_filter("3x", "--filter-mtime", now).is_err());
+        assert!(parse_time_filter("==3d", "--filter-mtime", now).is_err());
+        assert!(parse_time_filter("2025-13-01", "--filter-mtime", now).is_err());
+    }
+
+    #[test]
+    fn size_filter_supports_ranges_and_clauses() {
+        let range = parse_size_filter("10k..1M", "--filter-size").unwrap();
+        assert!(!range.allows(Some(1024)));
+        assert!(range.allows(Some(10 * 1024)));
+        assert!(range.allows(Some(1 << 20)));
+        assert!(!range.allows(Some((1 << 20) + 1)));
+
+        let clauses = parse_size_filter(">=10k, <1M", "--filter-size").unwrap();
+        assert!(clauses.allows(Some(512 * 1024)));
+        assert!(!clauses.allows(Some(1 << 20)));
+        assert!(!clauses.allows(None));
+
+        assert!(parse_size_filter("..", "--filter-size").is_err());
+        assert!(parse_size_filter(">1MB,", "--filter-size").is_err());
+        assert!(parse_size_filter("1MB", "--filter-size").is_err());
+    }
+
+    #[test]
+    fn time_filters_keep_fresh_directories_with_stale_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/readme.md"), "old").unwrap();
+        fs::write(root.join("docs/new.md"), "new").unwrap();
+        let stale = UNIX_EPOCH + Duration::from_secs(1_577_836_800); // 2020-01-01
+        fs::File::options()
+            .write(true)
+            .open(root.join("docs/readme.md"))
+            .unwrap()
+            .set_modified(stale)
+            .unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        assert_eq!(
+            collect_names(&[root_arg, "--filter-mtime", ">30d"]),
+            vec!["docs", "readme.md"]
+        );
+    }
+
+    #[test]
+    fn table_columns_follow_enabled_features() {
+        let columns = |args: &[&str]| {
+            let cli = Cli::parse_from(std::iter::once("printree").chain(args.iter().copied()));
+            TableColumns::from_cli(&cli).names()
+        };
+        assert!(columns(&["."]).is_empty());
+        assert_eq!(
+            columns(&[".", "--git-log", "--lfs"]),
+            vec![
+                "last_commit",
+                "last_author",
+                "last_date",
+                "last_subject",
+                "lfs_oid",
+                "lfs_local"
+            ]
+        );
+        assert_eq!(
+            columns(&[".", "--git-rename"]),
+            vec!["renamed_from", "similarity", "submodule"]
+        );
+        assert_eq!(columns(&["churn"]).len(), 4);
+        assert_eq!(
+            columns(&[".", "--max-entries-per-dir", "2"]),
+            vec!["truncated"]
+        );
+
+        let cli = Cli::parse_from(["printree", ".", "--source", "index"]);
+        let columns = TableColumns::from_cli(&cli);
+        let mut entry = csv_trailer_entry(BudgetLimit::Entries(1));
+        entry.stage = Some(2);
+        let mut row = Vec::new();
+        write_csv_entry(&mut row, &entry, &columns).unwrap();
+        assert!(String::from_utf8(row).unwrap().ends_with(",,2\n"));
+    }
+
+    #[test]
+    fn dir_size_index_sums_nested_files() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+        fs::write(root.join("a/one.bin"), vec![0u8; 100]).unwrap();
+        fs::write(root.join("a/b/two.bin"), vec![0u8; 50]).unwrap();
+        fs::write(root.join("a/b/.hidden"), vec![0u8; 7]).unwrap();
+
+        let index = DirSizeIndex::build(root, false, None);
+        assert_eq!(index.total_for(&root.join("a")), Some(150));
+        assert_eq!(index.total_for(&root.join("a/b")), Some(50));
+        assert_eq!(index.total_for(&root.join("empty")), Some(0));
+        assert_eq!(index.total_for(root), Some(150));
+
+        let with_hidden = DirSizeIndex::build(root, true, None);
+        assert_eq!(with_hidden.total_for(&root.join("a")), Some(157));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dir_size_filter_passes_symlinked_directories_through() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("big")).unwrap();
+        fs::create_dir_all(root.join("small")).unwrap();
+        fs::write(root.join("big/a.bin"), vec![0u8; 2000]).unwrap();
+        fs::write(root.join("small/b.bin"), vec![0u8; 10]).unwrap();
+        std::os::unix::fs::symlink("small", root.join("link")).unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        assert_eq!(
+            collect_names(&[root_arg, "--sort", "name", "--filter-dir-size", ">1000"]),
+            vec!["big", "a.bin", "link"]
+        );
+    }
+
+    #[test]
+    fn dir_size_index_keeps_siblings_of_hidden_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        // 読み出し順に関わらず、隠しディレクトリの前後どちらの兄弟も数える
+        for name in ["a", "m", "z"] {
+            fs::create_dir_all(root.join(name)).unwrap();
+            fs::write(root.join(name).join("f"), vec![0u8; 3]).unwrap();
+        }
+        fs::create_dir_all(root.join(".git/objects")).unwrap();
+        fs::write(root.join(".git/objects/pack"), vec![0u8; 1000]).unwrap();
+        fs::create_dir_all(root.join("empty")).unwrap();
+
+        let index = DirSizeIndex::build(root, false, None);
+        for name in ["a", "m", "z"] {
+            assert_eq!(index.total_for(&root.join(name)), Some(3), "{name}");
+        }
+        assert_eq!(index.total_for(&root.join("empty")), Some(0));
+        assert_eq!(index.total_for(root), Some(9));
+        assert_eq!(index.total_for(&root.join(".git")), None);
+    }
+
+    #[test]
+    fn perm_spec_supports_find_style_modes() {
+        let exact = parse_perm_spec("755").unwrap();
+        assert!(exact.allows(Some(0o100755)));
+        assert!(!exact.allows(Some(0o104755)));
+
+        let setuid = parse_perm_spec("-4000").unwrap();
+        assert!(setuid.allows(Some(0o104755)));
+        assert!(!setuid.allows(Some(0o100755)));
+
+        let any_write = parse_perm_spec("/022").unwrap();
+        assert!(any_write.allows(Some(0o100664)));
+        assert!(!any_write.allows(Some(0o100644)));
+        assert!(!any_write.allows(None));
+    }
+
+    #[test]
+    fn perm_spec_parses_symbolic_forms() {
+        assert_eq!(parse_symbolic_perm("u+x"), Some(0o100));
+        assert_eq!(parse_symbolic_perm("o+w"), Some(0o002));
+        assert_eq!(parse_symbolic_perm("+x"), Some(0o111));
+        assert_eq!(parse_symbolic_perm("ug+rw,+t"), Some(0o1660));
+        assert_eq!(parse_symbolic_perm("ug+s"), Some(0o6000));
+        assert_eq!(parse_symbolic_perm("u-x"), None);
+        assert_eq!(parse_symbolic_perm("z+x"), None);
+        assert_eq!(parse_symbolic_perm("u=rwx,go=rx"), Some(0o755));
+        assert_eq!(parse_symbolic_perm("a+x,u=rw"), Some(0o611));
+        assert_eq!(parse_symbolic_perm("o="), Some(0));
+        assert_eq!(parse_symbolic_perm("u+"), None);
+
+        let exact = parse_perm_spec("u=rwx").unwrap();
+        assert_eq!(exact.mode, PermMatch::Exact);
+        assert!(exact.allows(Some(0o100700)));
+        assert!(!exact.allows(Some(0o100744)));
+        assert!(parse_perm_spec("-u=rwx").unwrap().allows(Some(0o100744)));
+
+        let any_exec = parse_perm_spec("/a+x").unwrap();
+        assert_eq!(any_exec.mode, PermMatch::Any);
+        assert!(any_exec.allows(Some(0o100744)));
+        let any_none = parse_perm_spec("/000").unwrap();
+        assert!(any_none.allows(Some(0o100000)));
+        assert!(any_none.allows(Some(0o100644)));
+        assert!(parse_perm_spec("75").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn perm_filters_flag_directories_and_keep_ancestors_of_matches() {
+        use std::os::unix::fs::PermissionsExt;
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for (dir, file, file_mode) in [
+            ("open", "a.txt", 0o644),
+            ("closed", "ww.txt", 0o666),
+            ("plain", "b.txt", 0o644),
+        ] {
+            fs::create_dir(root.join(dir)).unwrap();
+            fs::write(root.join(dir).join(file), "").unwrap();
+            let mode = fs::Permissions::from_mode(file_mode);
+            fs::set_permissions(root.join(dir).join(file), mode).unwrap();
+            let mode = fs::Permissions::from_mode(if dir == "open" { 0o777 } else { 0o755 });
+            fs::set_permissions(root.join(dir), mode).unwrap();
+        }
+        let root_arg = root.to_str().unwrap();
+
+        // open/ は自身が一致、closed/ は一致する ww.txt への経路としてだけ出る
+        let expected = vec!["closed", "ww.txt", "open"];
+        assert_eq!(
+            collect_names(&[root_arg, "--sort", "name", "--world-writable"]),
+            expected
+        );
+        assert_eq!(
+            collect_names(&[root_arg, "--sort", "name", "--filter-perm", "/o+w"]),
+            expected
+        );
+    }
+
+    #[test]
+    fn prune_hides_directories_without_matching_descendants() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/b")).unwrap();
+        fs::create_dir_all(root.join("empty/deep")).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("a/b/x.rs"), "").unwrap();
+        fs::write(root.join("docs/readme.md"), "").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let unpruned = collect_names(&[root_arg, "--ext", "rs", "--sort", "name"]);
+        assert!(unpruned.contains(&"empty".to_string()));
+
+        let pruned = collect_names(&[root_arg, "--ext", "rs", "--sort", "name", "--prune"]);
+        assert_eq!(pruned, vec!["a", "b", "x.rs"]);
+
+        // max-depth で打ち切られても、一致する子孫があるディレクトリは残す
+        let shallow = collect_names(&[
+            root_arg,
+            "--ext",
+            "rs",
+            "--sort",
+            "name",
+            "--prune",
+            "--max-depth",
+            "2",
+        ]);
+        assert_eq!(shallow, vec!["a", "b"]);
+
+        // 先読みで絞り込んだディレクトリは、走査がそのまま取り出して読み直さない
+        let cli = Cli::parse_from(["printree", root_arg, "--ext", "rs", "--prune"]);
+        let matcher = Matcher::from_cli(&cli, root).unwrap();
+        let filters = Filters::from_cli(&cli, root).unwrap();
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        let jobs = JobPool::new(&cli).unwrap();
+        let ctx = WalkContext {
+            root,
+            cli: &cli,
+            matcher: &matcher,
+            filters: &filters,
+            git: &git,
+            jobs: &jobs,
+        };
+        assert!(dir_has_visible_entries(&ctx, &root.join("a"), 2).unwrap());
+        assert!(filters.lookahead.contains(&root.join("a/b")));
+        assert!(!dir_has_visible_entries(&ctx, &root.join("empty"), 2).unwrap());
+        let entries = collect_entries_flat(&ctx, &mut OutputBudget::new(&cli)).unwrap();
+        assert_eq!(entries.len(), 4);
+        assert!(filters.lookahead.frames.borrow().is_empty());
+    }
+
+    #[test]
+    fn natural_order_compares_digit_runs_numerically() {
+        let mut names = vec!["file10", "file2", "File1", "file02", "a"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, vec!["a", "File1", "file2", "file02", "file10"]);
+    }
+
+    #[test]
+    fn size_sort_uses_recursive_directory_totals() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("big/nested")).unwrap();
+        fs::write(root.join("big/nested/blob"), vec![0u8; 4096]).unwrap();
+        fs::write(root.join("medium.txt"), vec![0u8; 1024]).unwrap();
+        fs::write(root.join("small.txt"), "x").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let largest_first = collect_names(&[root_arg, "--sort", "size", "--max-depth", "2"]);
+        assert_eq!(
+            largest_first,
+            vec!["big", "nested", "medium.txt", "small.txt"]
+        );
+
+        let reversed =
+            collect_names(&[root_arg, "--sort", "size", "--reverse", "--max-depth", "2"]);
+        assert_eq!(reversed, vec!["small.txt", "medium.txt", "big", "nested"]);
+
+        // --dirs-first は選んだ並び順を崩さずにディレクトリを前へ寄せる
+        let dirs_first = collect_names(&[
+            root_arg,
+            "--sort",
+            "size",
+            "--reverse",
+            "--dirs-first",
+            "--max-depth",
+            "2",
+        ]);
+        assert_eq!(dirs_first, vec!["big", "nested", "small.txt", "medium.txt"]);
+    }
+
+    #[test]
+    fn ext_and_kind_sorts_group_entries() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("zdir")).unwrap();
+        fs::write(root.join("b.rs"), "").unwrap();
+        fs::write(root.join("a.toml"), "").unwrap();
+        fs::write(root.join("c.RS"), "").unwrap();
+        fs::write(root.join("Makefile"), "").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let by_ext = collect_names(&[root_arg, "--sort", "ext"]);
+        assert_eq!(by_ext, vec!["Makefile", "zdir", "b.rs", "c.RS", "a.toml"]);
+
+        let by_kind = collect_names(&[root_arg, "--sort", "kind"]);
+        assert_eq!(by_kind, vec!["zdir", "Makefile", "a.toml", "b.rs", "c.RS"]);
+    }
+
+    #[test]
+    fn collation_keys_normalize_width_case_and_kana() {
+        let key = |name: &str, collation| collation_key(&OsString::from(name), collation);
+        // NFD の「が」と NFC の「が」は同じキーになる
+        assert_eq!(
+            key("\u{304B}\u{3099}", Collation::Unicode),
+            key("\u{304C}", Collation::Unicode)
+        );
+        assert_eq!(key("ＡＢＣ", Collation::Unicode), "abc");
+        assert_eq!(key("ＡＢＣ", Collation::AsciiCi), "ＡＢＣ");
+        assert_eq!(key("README", Collation::AsciiCi), "readme");
+        assert_eq!(key("カナ", Collation::Ja), "かな");
+        assert_eq!(key("ｶﾅ", Collation::Ja), "かな");
+    }
+
+    #[test]
+    fn name_sort_with_collation_interleaves_forms() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        for name in ["b.txt", "Ａ.txt", "C.txt", "いぬ", "カメ", "あり"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let root_arg = root.to_str().unwrap();
+
+        let raw = collect_names(&[root_arg, "--sort", "name"]);
+        assert_eq!(
+            raw,
+            vec!["C.txt", "b.txt", "あり", "いぬ", "カメ", "Ａ.txt"]
+        );
+
+        let ja = collect_names(&[root_arg, "--sort", "name", "--collation", "ja"]);
+        assert_eq!(ja, vec!["Ａ.txt", "b.txt", "C.txt", "あり", "いぬ", "カメ"]);
+    }
+
+    #[test]
+    fn max_entries_per_dir_appends_a_truncated_summary() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("logs")).unwrap();
+        for (idx, name) in ["a.log", "b.log", "c.log", "d.log"].iter().enumerate() {
+            fs::write(root.join("logs").join(name), vec![0u8; 100 * (idx + 1)]).unwrap();
+        }
+        fs::create_dir_all(root.join("logs/old")).unwrap();
+        fs::write(root.join("logs/old/e.log"), vec![0u8; 1000]).unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let names = collect_names(&[
+            root_arg,
+            "--sort",
+            "name",
+            "--max-entries-per-dir",
+            "2",
+            "--max-depth",
+            "2",
+        ]);
+        assert_eq!(names, vec!["logs", "a.log", "b.log", "… 3 more entries"]);
+
+        let entries = collect_entries(&[root_arg, "--sort", "name", "--max-entries-per-dir", "3"]);
+        let summary = entries.last().unwrap();
+        assert!(matches!(summary.kind, EntryKind::Truncated));
+        assert_eq!(summary.truncated, Some(2));
+        // 省略された d.log と old/（配下の合計）のサイズ
+        assert_eq!(summary.size, Some(400 + 1000));
+
+        // ディレクトリごとの要約と出力全体の打ち切りは CSV/NDJSON の kind で見分ける
+        let cli = Cli::parse_from(["printree", root_arg, "--max-entries-per-dir", "3"]);
+        let columns = TableColumns::from_cli(&cli);
+        let kind_of = |entry: &Entry| {
+            let mut row = Vec::new();
+            write_csv_entry(&mut row, entry, &columns).unwrap();
+            let row = String::from_utf8(row).unwrap();
+            row.split(',').nth(3).unwrap().to_string()
+        };
+        assert_eq!(kind_of(summary), "truncated");
+        assert_eq!(
+            kind_of(&csv_trailer_entry(BudgetLimit::Entries(3))),
+            "budget"
+        );
+        let json = serde_json::to_value(summary).unwrap();
+        assert_eq!(json["kind"], "truncated");
+    }
+
+    #[test]
+    fn human_sizes_use_binary_units() {
+        assert_eq!(format_size_human(512), "512 B");
+        assert_eq!(format_size_human(1536), "1.5 KB");
+        assert_eq!(format_size_human(6_012_954_214), "5.6 GB");
+    }
+
+    #[test]
+    fn output_budget_reserves_the_trailer_and_stops_once() {
+        let cli = Cli::parse_from(["printree", ".", "--limit-output-bytes", "100"]);
+        let mut budget = OutputBudget::new(&cli);
+        budget.charge_fixed(20);
+        budget.reserve(30);
+        assert!(budget.admit(50));
+        assert!(!budget.admit(1));
+        assert_eq!(budget.exhausted(), Some(BudgetLimit::Bytes(100)));
+        // 一度打ち切ったら、収まる大きさでも受け付けない
+        budget.refund(40);
+        assert!(!budget.admit(1));
+        assert_eq!(budget.completion(), Completion::Truncated);
+
+        let cli = Cli::parse_from(["printree", ".", "--limit-entries", "2"]);
+        let mut budget = OutputBudget::new(&cli);
+        assert!(budget.admit(0));
+        assert!(budget.admit(0));
+        assert!(!budget.admit(0));
+        let record = serde_json::to_string(&budget.record(budget.exhausted().unwrap())).unwrap();
+        assert_eq!(
+            record,
+            r#"{"kind":"budget","reason":"limit-entries","limit":2,"entries":2}"#
+        );
+    }
+
+    #[test]
+    fn limit_entries_stops_traversal_in_walk_order() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir(root.join("a")).unwrap();
+        for name in ["a/x", "a/y", "b", "c"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let root_arg = root.to_str().unwrap();
+
+        let names = collect_names(&[root_arg, "--sort", "name", "--limit-entries", "3"]);
+        assert_eq!(names, vec!["a", "x", "y"]);
+    }
+
+    #[test]
+    fn git_status_keeps_index_and_worktree_columns_apart() {
+        let xy = |status: Status| GitStatus::from_git(status).map(|s| s.to_string());
+        assert_eq!(xy(Status::INDEX_MODIFIED).as_deref(), Some("M "));
+        assert_eq!(xy(Status::WT_MODIFIED).as_deref(), Some(" M"));
+        assert_eq!(
+            xy(Status::INDEX_MODIFIED | Status::WT_MODIFIED).as_deref(),
+            Some("MM")
+        );
+        assert_eq!(
+            xy(Status::INDEX_NEW | Status::WT_MODIFIED).as_deref(),
+            Some("AM")
+        );
+        assert_eq!(xy(Status::WT_NEW).as_deref(), Some("??"));
+        assert_eq!(xy(Status::CONFLICTED).as_deref(), Some("UU"));
+        assert_eq!(xy(Status::IGNORED).as_deref(), Some("!!"));
+        assert_eq!(xy(Status::CURRENT), None);
+
+        let staged = GitStatus::from_git(Status::INDEX_RENAMED).unwrap();
+        let unstaged = GitStatus::from_git(Status::WT_MODIFIED).unwrap();
+        assert_eq!(staged.merge(unstaged).to_string(), "RM");
+        assert!(staged.priority() > unstaged.priority());
+
+        let json = serde_json::to_string(&staged).unwrap();
+        assert_eq!(json, r#"{"index":"R","worktree":" "}"#);
+
+        let map = GitStatusMap {
+            workdir: PathBuf::from("/repo"),
+            cwd: PathBuf::from("/repo"),
+            statuses: HashMap::from([(PathBuf::from("target"), GitStatus::IGNORED)]),
+            ignored_dirs: HashSet::from([PathBuf::from("target")]),
+            rollups: HashMap::new(),
+            renames: HashMap::new(),
+            ghosts: HashMap::new(),
+            submodules: HashMap::new(),
+        };
+        assert_eq!(
+            map.status_for(Path::new("target/debug/app")),
+            Some(GitStatus::IGNORED)
+        );
+        assert_eq!(map.status_for(Path::new("src/main.rs")), None);
+    }
+
+    #[test]
+    fn ignored_entries_are_marked_only_with_git_ignored() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        fs::write(root.join(".gitignore"), "target/\n").unwrap();
+        commit_all(&repo, "init");
+        fs::create_dir_all(root.join("target")).unwrap();
+        fs::write(root.join("target/out.bin"), "").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let status_of = |args: &[&str], name: &str| {
+            collect_entries(args)
+                .into_iter()
+                .find(|e| e.name == name)
+                .and_then(|e| e.git_status.map(|s| s.to_string()))
+        };
+        assert_eq!(status_of(&[root_arg, "--git-status"], "target"), None);
+        assert_eq!(
+            status_of(&[root_arg, "--git-ignored"], "target").as_deref(),
+            Some("!!")
+        );
+        assert_eq!(
+            status_of(&[root_arg, "--git-ignored"], "out.bin").as_deref(),
+            Some("!!")
+        );
+    }
+
+    #[test]
+    fn git_rollups_count_changes_below_directories_and_prune_clean_ones() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        for name in ["a/changed.txt", "a/clean.txt", "b/clean.txt"] {
+            fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+            fs::write(root.join(name), "v1").unwrap();
+        }
+        commit_all(&repo, "init");
+        fs::write(root.join("a/changed.txt"), "v2").unwrap();
+        fs::create_dir_all(root.join("c")).unwrap();
+        fs::write(root.join("c/new.txt"), "").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let names = collect_names(&[root_arg, "--sort", "name", "--git-changed-only"]);
+        assert_eq!(names, vec!["a", "changed.txt", "c", "new.txt"]);
+
+        let cli = Cli::parse_from(["printree", root_arg, "--git-status"]);
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        let mut meta = EntryMeta::from_path(root);
+        git.apply(&mut meta);
+        let changes = meta.git_changes.unwrap();
+        assert_eq!(changes.to_string(), "M1 ?1");
+        assert_eq!(serde_json::to_string(&changes).unwrap(), r#"{"M":1,"?":1}"#);
+        let mut clean = EntryMeta::from_path(&root.join("b"));
+        git.apply(&mut clean);
+        assert_eq!(clean.git_changes, None);
+    }
+
+    #[test]
+    fn git_rename_reports_source_path_and_similarity() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        let body: String = (0..10).map(|n| format!("line {n}\n")).collect();
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::write(root.join("old/name.txt"), &body).unwrap();
+        commit_all(&repo, "init");
+
+        // 10 行中 1 行だけ書き換えてステージ済みの mv にする
+        fs::remove_file(root.join("old/name.txt")).unwrap();
+        fs::write(
+            root.join("new.txt"),
+            body.replace("line 9\n", "line nine\n"),
+        )
+        .unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("old/name.txt")).unwrap();
+        index.add_path(Path::new("new.txt")).unwrap();
+        index.write().unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let cli = Cli::parse_from(["printree", root_arg, "--git-rename"]);
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        let mut meta = EntryMeta::from_path(&root.join("new.txt"));
+        git.apply(&mut meta);
+        let entry = Entry::from_meta(&meta, 1);
+        assert_eq!(entry.renamed_from.as_deref(), Some("old/name.txt"));
+        assert_eq!(entry.similarity, Some(90));
+
+        let mut out = termcolor::NoColor::new(Vec::new());
+        write_plain_entry(&mut out, "", &entry, true).unwrap();
+        let line = String::from_utf8(out.into_inner()).unwrap();
+        assert!(line.contains("new.txt ← old/name.txt (90%)"), "{line}");
+
+        // しきい値を上げると削除と追加のまま
+        let cli = Cli::parse_from(["printree", root_arg, "--rename-threshold", "95"]);
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        let mut meta = EntryMeta::from_path(&root.join("new.txt"));
+        git.apply(&mut meta);
+        assert_eq!(meta.rename, None);
+
+        let cli = Cli::parse_from(["printree", root_arg, "--rename-threshold", "101"]);
+        assert!(GitTracker::prepare(root, &cli).is_err());
+    }
+
+    #[test]
+    fn git_submodules_report_their_state_and_inner_status() {
+        let tmp = tempfile::tempdir().unwrap();
+        let upstream_dir = tmp.path().join("upstream");
+        let upstream = Repository::init(&upstream_dir).unwrap();
+        fs::write(upstream_dir.join("lib.rs"), "pub fn f() {}\n").unwrap();
+        commit_all(&upstream, "c");
+
+        let root = tmp.path().join("product");
+        let repo = Repository::init(&root).unwrap();
+        fs::write(root.join("README"), "r").unwrap();
+        let mut submodule = repo
+            .submodule(upstream_dir.to_str().unwrap(), Path::new("libs/a"), true)
+            .unwrap();
+        let sub = submodule.clone(None).unwrap();
+        submodule.add_finalize().unwrap();
+        commit_all(&repo, "c");
+        // サブモジュールの中で進めたコミットと未追跡のファイル
+        fs::write(root.join("libs/a/lib.rs"), "pub fn g() {}\n").unwrap();
+        commit_all(&sub, "c");
+        fs::write(root.join("libs/a/scratch.txt"), "s").unwrap();
+        // 親が追跡していない入れ子のリポジトリ
+        Repository::init(root.join("nested")).unwrap();
+        fs::write(root.join("nested/inner.txt"), "i").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let find = |entries: &[Entry], name: &str| {
+            entries
+                .iter()
+                .find(|e| e.name == name)
+                .map(|e| (e.submodule, e.git_status.map(|s| s.to_string())))
+                .unwrap()
+        };
+
+        let entries = collect_entries(&[root_arg, "--sort", "name", "--git-status"]);
+        let (state, _) = find(&entries, "a");
+        let state = state.unwrap();
+        assert!(state.new_commits && state.dirty && !state.uninitialized);
+        assert_eq!(state.to_string(), "new commits, dirty");
+        // 既定では中までは見ない
+        assert_eq!(find(&entries, "scratch.txt").1, None);
+        assert_eq!(find(&entries, "inner.txt").1, None);
+
+        let entries = collect_entries(&[root_arg, "--sort", "name", "--git-submodules"]);
+        assert_eq!(find(&entries, "scratch.txt").1.as_deref(), Some("??"));
+        assert_eq!(find(&entries, "lib.rs").1, None);
+        assert_eq!(find(&entries, "inner.txt").1.as_deref(), Some("??"));
+    }
+
+    #[test]
+    fn lfs_pointers_report_object_size_oid_and_local_copy() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        Repository::init(root).unwrap();
+        let pointer = |oid: &str, size: u64| {
+            format!("version https://git-lfs.github.com/spec/v1\noid sha256:{oid}\nsize {size}\n")
+        };
+        let fetched = "a".repeat(64);
+        let missing = "b".repeat(64);
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::write(root.join("assets/hero.psd"), pointer(&fetched, 5_000_000)).unwrap();
+        fs::write(root.join("assets/intro.mp4"), pointer(&missing, 20_000_000)).unwrap();
+        fs::write(root.join("notes.txt"), "version 2\n").unwrap();
+        let objects = root.join(".git/lfs/objects/aa/aa");
+        fs::create_dir_all(&objects).unwrap();
+        fs::write(objects.join(&fetched), "").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let cli = Cli::parse_from(["printree", root_arg, "--lfs", "--sort", "size"]);
+        let mut filters = Filters::from_cli(&cli, root).unwrap();
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        filters.use_git(&cli, root, &git);
+        assert_eq!(
+            filters
+                .dir_sizes
+                .as_ref()
+                .unwrap()
+                .total_for(&root.join("assets")),
+            Some(25_000_000)
+        );
+        let entries = collect_entries(&[root_arg, "--lfs", "--sort", "size"]);
+        let rows: Vec<_> = entries
+            .iter()
+            .skip(2)
+            .map(|e| (e.name.as_str(), e.size, e.lfs.as_ref().map(|l| l.local)))
+            .collect();
+        // ディレクトリの合計も実体の大きさなので、assets が先頭に来る
+        assert_eq!(entries[1].name, "assets");
+        assert_eq!(
+            rows,
+            vec![
+                ("intro.mp4", Some(20_000_000), Some(false)),
+                ("hero.psd", Some(5_000_000), Some(true)),
+                ("notes.txt", Some(10), None),
+            ]
+        );
+        assert_eq!(entries[3].lfs.as_ref().unwrap().oid, fetched);
+
+        assert_eq!(
+            collect_names(&[root_arg, "--type", "lfs", "--sort", "name"]),
+            vec!["assets", "hero.psd", "intro.mp4"]
+        );
+    }
+
+    #[test]
+    fn git_ghosts_inject_deleted_files_and_directories() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        for name in ["gone/sub/x.txt", "keep/w.txt", "keep/z.txt"] {
+            fs::create_dir_all(root.join(name).parent().unwrap()).unwrap();
+            fs::write(root.join(name), "v1").unwrap();
+        }
+        commit_all(&repo, "init");
+        fs::remove_dir_all(root.join("gone")).unwrap();
+        fs::remove_file(root.join("keep/w.txt")).unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        assert_eq!(
+            collect_names(&[root_arg, "--sort", "name"]),
+            vec!["keep", "z.txt"]
+        );
+
+        let entries = collect_entries(&[root_arg, "--sort", "name", "--git-ghosts"]);
+        let rows: Vec<_> = entries
+            .iter()
+            .skip(1)
+            .map(|e| {
+                let status = e.git_status.map(|s| s.to_string());
+                (e.name.as_str(), e.ghost, status)
+            })
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("gone", true, None),
+                ("sub", true, None),
+                ("x.txt", true, Some(String::from(" D"))),
+                ("keep", false, None),
+                ("w.txt", true, Some(String::from(" D"))),
+                ("z.txt", false, None),
+            ]
+        );
+        assert!(matches!(entries[1].kind, EntryKind::Dir));
+        assert!(matches!(entries[3].kind, EntryKind::File));
+
+        assert_eq!(
+            collect_names(&[root_arg, "--sort", "name", "--git-ghosts", "--type", "dir"]),
+            vec!["gone", "sub", "keep"]
+        );
+    }
+
+    #[test]
+    fn git_log_maps_paths_and_directories_to_their_last_commit() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        fs::create_dir_all(root.join("old")).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("old/stale.txt"), "v1").unwrap();
+        fs::write(root.join("src/lib.rs"), "v1").unwrap();
+        let first = commit_all(&repo, "init\n\nbody");
+        fs::write(root.join("src/lib.rs"), "v2").unwrap();
+        let second = commit_all(&repo, "touch lib");
+        // --no-ff のマージ。feat.txt はマージではなく取り込んだコミットのものになる
+        fs::write(root.join("feat.txt"), "f").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("feat.txt")).unwrap();
+        index.write().unwrap();
+        let feat_tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let base = repo.find_commit(second).unwrap();
+        let sig = git2::Signature::now("t", "t@example.com").unwrap();
+        let feat = repo
+            .commit(None, &sig, &sig, "add feat", &feat_tree, &[&base])
+            .unwrap();
+        let merger = git2::Signature::now("merger", "m@example.com").unwrap();
+        let feat_commit = repo.find_commit(feat).unwrap();
+        repo.commit(
+            Some("HEAD"),
+            &merger,
+            &merger,
+            "Merge feat",
+            &feat_tree,
+            &[&base, &feat_commit],
+        )
+        .unwrap();
+        fs::write(root.join("untracked.txt"), "").unwrap();
+
+        let log = GitLogIndex::build(root, None).unwrap().unwrap();
+        let short = |oid: git2::Oid| oid.to_string()[..7].to_string();
+        let hash_of = |rel: &str| log.commit_for(&root.join(rel)).map(|c| c.hash.clone());
+        assert_eq!(hash_of("old/stale.txt"), Some(short(first)));
+        assert_eq!(hash_of("old"), Some(short(first)));
+        assert_eq!(hash_of("src/lib.rs"), Some(short(second)));
+        assert_eq!(hash_of("src"), Some(short(second)));
+        assert_eq!(hash_of("feat.txt"), Some(short(feat)));
+        assert_eq!(hash_of("untracked.txt"), None);
+
+        let info = log.commit_for(&root.join("old")).unwrap();
+        assert_eq!((info.author.as_str(), info.subject.as_str()), ("t", "init"));
+        assert_eq!(format_relative_age(0), "0 seconds ago");
+        assert_eq!(format_relative_age(3 * 86_400), "3 days ago");
+        assert_eq!(format_relative_age(400 * 86_400), "1 year ago");
+    }
+
+    #[test]
+    fn rev_walks_the_commit_tree_instead_of_the_working_tree() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        fs::create_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("docs/big.md"), vec![b'x'; 300]).unwrap();
+        fs::write(root.join("small.txt"), "abc").unwrap();
+        fs::write(root.join(".hidden"), "h").unwrap();
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("small.txt", root.join("link")).unwrap();
+        commit_all(&repo, "init");
+        // ワークツリーだけを変えても --rev の出力には出ない
+        fs::remove_dir_all(root.join("docs")).unwrap();
+        fs::write(root.join("new.txt"), "n").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let entries = collect_entries(&[root_arg, "--rev", "HEAD", "--sort", "size"]);
+        let rows: Vec<_> = entries
+            .iter()
+            .skip(1)
+            .map(|e| (e.name.as_str(), e.size, e.perm.as_deref()))
+            .collect();
+        let mut expected = vec![
+            ("docs", None, Some("40000")),
+            ("big.md", Some(300), Some("100644")),
+            ("small.txt", Some(3), Some("100644")),
+        ];
+        #[cfg(unix)]
+        expected.push(("link", None, Some("120000")));
+        #[cfg(unix)]
+        assert_eq!(entries[4].symlink_target.as_deref(), Some("small.txt"));
+        assert_eq!(rows, expected);
+        assert!(matches!(entries[1].kind, EntryKind::Dir));
+
+        assert_eq!(
+            collect_names(&[root_arg, "--rev", "HEAD", "--filter-size", ">100"]),
+            vec!["docs", "big.md"]
+        );
+        let cli = Cli::parse_from(["printree", root_arg, "--rev", "HEAD~1"]);
+        assert!(GitTracker::prepare(root, &cli).is_err());
+    }
+
+    #[test]
+    fn source_index_lists_staged_entries_and_conflict_stages() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        let repo = Repository::init(root).unwrap();
+        fs::create_dir_all(root.join("src")).unwrap();
+        fs::write(root.join("src/lib.rs"), "fn main() {}\n").unwrap();
+        fs::write(root.join("untracked.txt"), "u").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("src/lib.rs")).unwrap();
+        for (stage, body) in [(1u16, "base"), (2, "ours!"), (3, "theirs")] {
+            let entry = git2::IndexEntry {
+                ctime: git2::IndexTime::new(0, 0),
+                mtime: git2::IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: body.len() as u32,
+                id: repo.blob(body.as_bytes()).unwrap(),
+                flags: stage << GIT_INDEX_STAGE_SHIFT,
+                flags_extended: 0,
+                path: b"both.txt".to_vec(),
+            };
+            index.add(&entry).unwrap();
+        }
+        index.write().unwrap();
+        // ステージしていない変更は出ない
+        fs::write(root.join("src/lib.rs"), "changed").unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let entries = collect_entries(&[root_arg, "--source", "index", "--sort", "name"]);
+        let rows: Vec<_> = entries
+            .iter()
+            .skip(1)
+            .map(|e| (e.name.as_str(), e.size, e.stage))
+            .collect();
+        assert_eq!(
+            rows,
+            vec![
+                ("both.txt", Some(4), Some(1)),
+                ("both.txt", Some(5), Some(2)),
+                ("both.txt", Some(6), Some(3)),
+                ("src", None, None),
+                ("lib.rs", Some(13), Some(0)),
+            ]
+        );
+
+        let mut out = termcolor::NoColor::new(Vec::new());
+        write_plain_entry(&mut out, "", &entries[2], false).unwrap();
+        let line = String::from_utf8(out.into_inner()).unwrap();
+        assert!(line.ends_with("both.txt  [stage 2: ours]\n"), "{line}");
+
+        let cli = Cli::parse_from(["printree", root_arg, "--source", "index", "--rev", "HEAD"]);
+        assert!(GitTracker::prepare(root, &cli).is_err());
+    }
+
+    #[test]
+    fn tree_stats_count_kinds_extensions_and_largest() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src/deep")).unwrap();
+        for (idx, name) in [
+            "a.rs",
+            "b.RS",
+            "src/c.rs",
+            "src/d.toml",
+            "src/deep/Makefile",
+        ]
+        .iter()
+        .enumerate()
+        {
+            fs::write(root.join(name), vec![0u8; 10 * (idx + 1)]).unwrap();
+        }
+        for idx in 0..4 {
+            fs::write(root.join(format!("src/big{idx}.bin")), vec![0u8; 100 + idx]).unwrap();
+        }
+        #[cfg(unix)]
+        std::os::unix::fs::symlink("a.rs", root.join("link")).unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let entries = collect_entries(&[root_arg, "--sort", "name"]);
+        let mut stats = TreeStats::new();
+        for entry in entries.iter().skip(1) {
+            stats.record(entry);
+        }
+
+        assert_eq!((stats.directories, stats.files), (2, 9));
+        assert_eq!(stats.footer(), "\n2 directories, 9 files\n");
+        #[cfg(unix)]
+        assert_eq!(stats.symlinks, 1);
+        let record = stats.record_for_output();
+        assert_eq!(record.bytes, 150 + 406);
+        let sizes: Vec<u64> = record.largest.iter().map(|item| item.size).collect();
+        assert_eq!(sizes, vec![103, 102, 101, 100, 50]);
+        let exts: Vec<_> = record
+            .extensions
+            .iter()
+            .map(|item| (item.ext.as_deref(), item.count))
+            .collect();
+        assert_eq!(
+            exts,
+            vec![
+                (Some("bin"), 4),
+                (Some("rs"), 3),
+                (None, 1),
+                (Some("toml"), 1)
+            ]
+        );
+        let deepest = record.deepest.unwrap();
+        assert_eq!(deepest.depth, 3);
+        assert!(deepest.path.ends_with("Makefile"));
+        assert_eq!(plain_footer(1, 1), "\n1 directory, 1 file\n");
+    }
+
+    #[test]
+    fn bfs_walk_emits_entries_level_by_level() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("a/deep")).unwrap();
+        for name in ["a/x", "a/deep/z", "b"] {
+            fs::write(root.join(name), "").unwrap();
+        }
+        let root_arg = root.to_str().unwrap();
+
+        let cli = Cli::parse_from(["printree", root_arg, "--sort", "name", "--bfs"]);
+        let matcher = Matcher::from_cli(&cli, root).unwrap();
+        let filters = Filters::from_cli(&cli, root).unwrap();
+        let git = GitTracker::prepare(root, &cli).unwrap();
+        let jobs = JobPool::new(&cli).unwrap();
+        let ctx = WalkContext {
+            root,
+            cli: &cli,
+            matcher: &matcher,
+            filters: &filters,
+            git: &git,
+            jobs: &jobs,
+        };
+        let mut visited = HashSet::new();
+        let mut seen = Vec::new();
+        walk_entries(&ctx, &mut visited, None, |entry| {
+            seen.push((entry.depth, entry.name.clone()));
+            Ok(true)
+        })
+        .unwrap();
+        let expected = [(1, "a"), (1, "b"), (2, "deep"), (2, "x"), (3, "z")];
+        assert_eq!(
+            seen,
+            expected
+                .iter()
+                .map(|(depth, name)| (*depth, name.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn summary_depth_prints_totals_of_hidden_levels() {
+        let tmp = tempfile::tempdir().unwrap();
+        let root = tmp.path();
+        fs::create_dir_all(root.join("src/core/deep")).unwrap();
+        fs::write(root.join("src/lib.rs"), vec![0u8; 10]).unwrap();
+        fs::write(root.join("src/core/a.rs"), vec![0u8; 20]).unwrap();
+        fs::write(root.join("src/core/deep/b.rs"), vec![0u8; 30]).unwrap();
+        fs::write(root.join("README"), vec![0u8; 5]).unwrap();
+        let root_arg = root.to_str().unwrap();
+
+        let entries = collect_entries(&[root_arg, "--sort", "name", "--summary-depth", "1"]);
+
+        let names: Vec<_> = entries.iter().skip(1).map(|e| e.name.as_str()).collect();
+        assert_eq!(names, vec!["README", "src"]);
+        let src = &entries[2];
+        assert_eq!(
+            (src.files, src.dirs, src.size),
+            (Some(3), Some(2), Some(60))
+        );
+        assert_eq!((entries[0].files, entries[0].dirs), (Some(4), Some(3)));
+        assert_eq!(entries[1].files, None);
+    }
+}
@@ -1,14 +1,14 @@
 use anyhow::Result;
-use ignore::{overrides::OverrideBuilder, WalkBuilder};
+use ignore::WalkBuilder;
 use serde::Serialize;
 use std::io::Write;
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
 use termcolor::{Color, ColorSpec, StandardStream, WriteColor};
 
-use crate::cli::{Cli, Format};
-use crate::utils::{
-    allow_type, build_include_prefixes, build_patterns, color_choice, include_dir_allowed,
-};
+use crate::cli::{Cli, Format, Source};
+use crate::core::tree::Completion;
+use crate::utils::{allow_type, color_choice, Matcher};
 
 #[derive(Serialize)]
 struct JsonEntry<'a> {
@@ -20,12 +20,58 @@ struct JsonEntry<'a> {
     error: Option<&'a str>,
 }
 
-pub fn run_tree_gitignore(cli: &Cli) -> Result<()> {
+pub fn run_tree_gitignore(cli: &Cli) -> Result<Completion> {
+    if cli.prune {
+        eprintln!("[warn] --prune ignored with --gitignore on");
+    }
+    if cli.limit_entries.is_some() || cli.limit_output_bytes.is_some() {
+        eprintln!("[warn] --limit-entries/--limit-output-bytes ignored with --gitignore on");
+    }
+    if cli.summary_depth.is_some() || cli.bfs {
+        eprintln!("[warn] --summary-depth/--bfs ignored with --gitignore on");
+    }
+    if cli.stats {
+        eprintln!("[warn] --stats ignored with --gitignore on");
+    }
+    if cli.git_status || cli.git_changed_only || cli.git_ghosts || cli.git_submodules || cli.git_log
+    {
+        eprintln!(
+            "[warn] --git-status/--git-changed-only/--git-ghosts/--git-submodules/--git-log ignored with --gitignore on"
+        );
+    }
+    if cli.rev.is_some() {
+        eprintln!("[warn] --rev ignored with --gitignore on");
+    }
+    if cli.lfs {
+        eprintln!("[warn] --lfs ignored with --gitignore on");
+    }
+    if cli.source == Source::Index {
+        eprintln!("[warn] --source index ignored with --gitignore on");
+    }
     if cli.format == Format::Json {
-        run_tree_gitignore_json(cli)
+        run_tree_gitignore_json(cli)?;
     } else {
-        run_tree_gitignore_plain(cli)
+        run_tree_gitignore_plain(cli)?;
     }
+    Ok(Completion::Complete)
+}
+
+/// gitignore を尊重するウォーカー。include/exclude などは [`Matcher`] で判定し、
+/// 除外されたディレクトリには降りない。
+fn walk_builder(cli: &Cli, root: &Path, matcher: Arc<Matcher>) -> WalkBuilder {
+    let mut wb = WalkBuilder::new(root);
+    wb.hidden(!cli.hidden)
+        .git_ignore(true)
+        .git_global(true)
+        .git_exclude(true)
+        .follow_links(cli.follow_symlinks)
+        .max_depth(cli.max_depth)
+        .standard_filters(false)
+        .filter_entry(move |d| {
+            let is_dir = d.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
+            d.depth() == 0 || matcher.allows(d.path(), is_dir)
+        });
+    wb
 }
 
 fn run_tree_gitignore_plain(cli: &Cli) -> Result<()> {
@@ -37,32 +83,9 @@ fn run_tree_gitignore_plain(cli: &Cli) -> Result<()> {
     writeln!(&mut out, "{}", root_path.display())?;
     out.reset()?;
 
-    let include_glob = build_patterns(&cli.includes, cli.pattern_syntax, true)?;
-    let exclude_glob = build_patterns(&cli.excludes, cli.pattern_syntax, false)?;
-    let include_prefixes =
-        build_include_prefixes(root_path, &cli.includes, cli.pattern_syntax, cli.match_mode);
-
-    let mut ov = OverrideBuilder::new(&root);
-    for exc in &cli.excludes {
-        ov.add(exc).ok();
-    }
-    for inc in &cli.includes {
-        ov.add(&format!("!{}", inc)).ok();
-    }
-    let overrides = ov.build().ok();
-
-    let mut wb = WalkBuilder::new(&root);
-    wb.hidden(!cli.hidden)
-        .git_ignore(true)
-        .git_global(true)
-        .git_exclude(true)
-        .follow_links(cli.follow_symlinks)
-        .max_depth(cli.max_depth)
-        .standard_filters(false);
-    if let Some(o) = overrides {
-        wb.overrides(o);
-    }
+    let matcher = Arc::new(Matcher::from_cli(cli, root_path)?);
 
+    let wb = walk_builder(cli, root_path, matcher);
     for dent in wb.build() {
         match dent {
             Ok(d) => {
@@ -78,42 +101,6 @@ fn run_tree_gitignore_plain(cli: &Cli) -> Result<()> {
                     }
                 }
 
-                let target: PathBuf = match cli.match_mode {
-                    crate::cli::MatchMode::Name => {
-                        path.file_name().map(PathBuf::from).unwrap_or_default()
-                    }
-                    crate::cli::MatchMode::Path => {
-                        path.strip_prefix(root_path).unwrap_or(path).to_path_buf()
-                    }
-                };
-
-                let excluded = exclude_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(false);
-                if excluded {
-                    continue;
-                }
-
-                let include_ok = include_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(true);
-
-                let is_dir = d.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-                let allow_dir = is_dir
-                    && include_dir_allowed(
-                        root_path,
-                        path,
-                        &include_glob,
-                        &include_prefixes,
-                        cli.match_mode,
-                    );
-
-                if !include_ok && !allow_dir {
-                    continue;
-                }
-
                 for _ in 0..depth {
                     write!(&mut out, "    ")?;
                 }
@@ -143,10 +130,7 @@ fn run_tree_gitignore_plain(cli: &Cli) -> Result<()> {
 fn run_tree_gitignore_json(cli: &Cli) -> Result<()> {
     let root = cli.path.clone().unwrap_or_else(|| ".".into());
     let root_path = Path::new(&root);
-    let include_glob = build_patterns(&cli.includes, cli.pattern_syntax, true)?;
-    let exclude_glob = build_patterns(&cli.excludes, cli.pattern_syntax, false)?;
-    let include_prefixes =
-        build_include_prefixes(root_path, &cli.includes, cli.pattern_syntax, cli.match_mode);
+    let matcher = Arc::new(Matcher::from_cli(cli, root_path)?);
     let mut stdout = std::io::BufWriter::new(std::io::stdout().lock());
 
     // ルート
@@ -163,27 +147,7 @@ fn run_tree_gitignore_json(cli: &Cli) -> Result<()> {
     )?;
     writeln!(&mut stdout)?;
 
-    let mut ov = OverrideBuilder::new(&root);
-    for exc in &cli.excludes {
-        ov.add(exc).ok();
-    }
-    for inc in &cli.includes {
-        ov.add(&format!("!{}", inc)).ok();
-    }
-    let overrides = ov.build().ok();
-
-    let mut wb = WalkBuilder::new(&root);
-    wb.hidden(!cli.hidden)
-        .git_ignore(true)
-        .git_global(true)
-        .git_exclude(true)
-        .follow_links(cli.follow_symlinks)
-        .max_depth(cli.max_depth)
-        .standard_filters(false);
-    if let Some(o) = overrides {
-        wb.overrides(o);
-    }
-
+    let wb = walk_builder(cli, root_path, matcher);
     for dent in wb.build() {
         match dent {
             Ok(d) => {
@@ -199,42 +163,6 @@ fn run_tree_gitignore_json(cli: &Cli) -> Result<()> {
                     }
                 }
 
-                let target: PathBuf = match cli.match_mode {
-                    crate::cli::MatchMode::Name => {
-                        path.file_name().map(PathBuf::from).unwrap_or_default()
-                    }
-                    crate::cli::MatchMode::Path => {
-                        path.strip_prefix(root_path).unwrap_or(path).to_path_buf()
-                    }
-                };
-
-                let excluded = exclude_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(false);
-                if excluded {
-                    continue;
-                }
-
-                let include_ok = include_glob
-                    .as_ref()
-                    .map(|gs| gs.is_match(&target))
-                    .unwrap_or(true);
-
-                let is_dir = d.file_type().map(|ft| ft.is_dir()).unwrap_or(false);
-                let allow_dir = is_dir
-                    && include_dir_allowed(
-                        root_path,
-                        path,
-                        &include_glob,
-                        &include_prefixes,
-                        cli.match_mode,
-                    );
-
-                if !include_ok && !allow_dir {
-                    continue;
-                }
-
                 let name = path.file_name().unwrap_or_default().to_string_lossy();
                 let path_s = path.display().to_string();
                 let kind = match d.file_type() {
@@ -293,22 +221,53 @@ mod tests {
             cmd: None,
             path: Some(root.to_path_buf()),
             max_depth: None,
+            summary_depth: None,
+            bfs: false,
+            max_entries_per_dir: None,
+            limit_entries: None,
+            limit_output_bytes: None,
+            no_report: false,
+            stats: false,
             hidden: true,
             follow_symlinks: false,
             sort: crate::cli::SortMode::Name,
+            reverse: false,
+            collation: None,
+            check_normalization: false,
             dirs_first: true,
             includes: vec![],
             excludes: vec![],
+            exts: vec![],
+            langs: vec![],
+            list_langs: false,
+            ignore_case: false,
+            smart_case: false,
+            prune: false,
             pattern_syntax: crate::cli::PatternSyntax::Glob,
             match_mode: crate::cli::MatchMode::Path,
             filter_regex: None,
             filter_size: None,
+            filter_dir_size: None,
             filter_mtime: None,
+            filter_ctime: None,
+            filter_atime: None,
             filter_perm: None,
+            world_writable: false,
+            executable: false,
+            setuid: false,
             types: vec![],
             gitignore: crate::cli::GitignoreMode::On,
             git_status: false,
+            git_changed_only: false,
+            git_ghosts: false,
+            git_submodules: false,
+            git_ignored: false,
+            git_log: false,
+            lfs: false,
+            rev: None,
+            source: Source::Worktree,
             git_rename: false,
+            rename_threshold: None,
             color: crate::cli::ColorMode::Never,
             format: crate::cli::Format::Json,
             encoding: crate::cli::EncodingMode::Utf8,
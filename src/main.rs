@@ -33,6 +33,9 @@ fn set_console_encoding(encoding: &crate::cli::EncodingMode) {
     }
 }
 
+/// `--limit-entries` / `--limit-output-bytes` で出力を打ち切ったときの終了コード
+const TRUNCATED_EXIT_CODE: i32 = 3;
+
 fn main() -> Result<()> {
     #[cfg(windows)]
     enable_utf8_output();
@@ -41,6 +44,12 @@ fn main() -> Result<()> {
     #[cfg(windows)]
     set_console_encoding(&cli.encoding);
 
+    if cli.list_langs {
+        let mut stdout = std::io::stdout().lock();
+        utils::write_lang_list(&mut stdout)?;
+        return Ok(());
+    }
+
     match &cli.cmd {
         Some(Cmd::Diff {
             rev_a,
@@ -48,9 +57,35 @@ fn main() -> Result<()> {
             path,
             format,
         }) => core::diff::run_diff(rev_a, rev_b, path.as_deref(), *format),
-        None => match cli.gitignore {
-            GitignoreMode::On => core::tree_gitignore::run_tree_gitignore(&cli),
-            GitignoreMode::Off => core::tree::run_tree(&cli),
-        },
+        Some(Cmd::Churn { path, since }) => {
+            let completion =
+                core::churn::run_churn(&cli, path.as_deref(), since.as_deref())?;
+            if completion == core::tree::Completion::Truncated {
+                std::process::exit(TRUNCATED_EXIT_CODE);
+            }
+            Ok(())
+        }
+        Some(Cmd::Stats {
+            path,
+            depth,
+            format,
+        }) => core::stats::run_stats(&cli, path.as_deref(), *depth, *format),
+        Some(Cmd::Top {
+            path,
+            by,
+            n,
+            tree,
+            format,
+        }) => core::top::run_top(&cli, path.as_deref(), *by, *n, *tree, *format),
+        None => {
+            let completion = match cli.gitignore {
+                GitignoreMode::On => core::tree_gitignore::run_tree_gitignore(&cli)?,
+                GitignoreMode::Off => core::tree::run_tree(&cli)?,
+            };
+            if completion == core::tree::Completion::Truncated {
+                std::process::exit(TRUNCATED_EXIT_CODE);
+            }
+            Ok(())
+        }
     }
 }
@@ -1,15 +1,16 @@
 use anyhow::{anyhow, Context, Result};
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use regex_automata::meta::Regex;
-use std::collections::HashSet;
+use regex_automata::{Input, PatternSet};
 use std::ffi::OsStr;
 use std::fs;
+use std::io::Write;
 use std::path::{Path, PathBuf};
 use termcolor::ColorChoice;
 
-use crate::cli::args::{ColorMode, MatchMode, PatternSyntax, TypeFilter};
+use crate::cli::args::{Cli, ColorMode, MatchMode, PatternSyntax, TypeFilter};
 
-pub enum PatternList {
+enum PatternList {
     Glob(GlobSet),
     Regex(Regex),
 }
@@ -18,155 +19,659 @@ pub fn is_hidden(name: &OsStr) -> bool {
     name.to_string_lossy().starts_with('.')
 }
 
-pub fn build_patterns(
-    patterns: &[String],
-    syntax: PatternSyntax,
-    allow_partial: bool,
-) -> Result<Option<PatternList>> {
-    if patterns.is_empty() {
-        return Ok(None);
-    }
-
-    match syntax {
-        PatternSyntax::Glob => {
-            let mut builder = GlobSetBuilder::new();
-            for p in patterns {
-                let pattern = if allow_partial && !contains_glob_meta(p) {
-                    format!("*{p}*")
-                } else {
-                    p.clone()
-                };
-                builder
-                    .add(Glob::new(&pattern).with_context(|| format!("invalid glob: {pattern}"))?);
+/// 拡張子・ファイル名の組で表した組み込み言語定義
+pub struct LangDef {
+    pub name: &'static str,
+    pub exts: &'static [&'static str],
+    pub names: &'static [&'static str],
+}
+
+pub const LANGS: &[LangDef] = &[
+    LangDef {
+        name: "c",
+        exts: &["c", "h"],
+        names: &[],
+    },
+    LangDef {
+        name: "cpp",
+        exts: &["cc", "cpp", "cxx", "c++", "h", "hh", "hpp", "hxx", "inl"],
+        names: &[],
+    },
+    LangDef {
+        name: "csharp",
+        exts: &["cs", "csx", "csproj", "sln"],
+        names: &[],
+    },
+    LangDef {
+        name: "css",
+        exts: &["css", "scss", "sass", "less"],
+        names: &[],
+    },
+    LangDef {
+        name: "docker",
+        exts: &["dockerfile"],
+        names: &["Dockerfile", ".dockerignore"],
+    },
+    LangDef {
+        name: "go",
+        exts: &["go"],
+        names: &["go.mod", "go.sum"],
+    },
+    LangDef {
+        name: "html",
+        exts: &["html", "htm", "xhtml"],
+        names: &[],
+    },
+    LangDef {
+        name: "java",
+        exts: &["java", "jsp", "properties"],
+        names: &[],
+    },
+    LangDef {
+        name: "js",
+        exts: &["js", "mjs", "cjs", "jsx"],
+        names: &[],
+    },
+    LangDef {
+        name: "json",
+        exts: &["json", "jsonl", "ndjson"],
+        names: &[],
+    },
+    LangDef {
+        name: "kotlin",
+        exts: &["kt", "kts"],
+        names: &[],
+    },
+    LangDef {
+        name: "make",
+        exts: &["mk", "mak"],
+        names: &["Makefile", "makefile", "GNUmakefile"],
+    },
+    LangDef {
+        name: "markdown",
+        exts: &["md", "markdown", "mdx"],
+        names: &[],
+    },
+    LangDef {
+        name: "php",
+        exts: &["php", "phtml"],
+        names: &[],
+    },
+    LangDef {
+        name: "python",
+        exts: &["py", "pyi", "pyw"],
+        names: &["requirements.txt", "pyproject.toml", "setup.cfg"],
+    },
+    LangDef {
+        name: "ruby",
+        exts: &["rb", "gemspec", "rake"],
+        names: &["Gemfile", "Rakefile"],
+    },
+    LangDef {
+        name: "rust",
+        exts: &["rs"],
+        names: &["Cargo.toml", "Cargo.lock"],
+    },
+    LangDef {
+        name: "shell",
+        exts: &["sh", "bash", "zsh", "fish", "ksh"],
+        names: &[],
+    },
+    LangDef {
+        name: "sql",
+        exts: &["sql"],
+        names: &[],
+    },
+    LangDef {
+        name: "toml",
+        exts: &["toml"],
+        names: &[],
+    },
+    LangDef {
+        name: "ts",
+        exts: &["ts", "tsx", "mts", "cts"],
+        names: &[],
+    },
+    LangDef {
+        name: "vb",
+        exts: &["vb", "vbs", "vbproj", "bas", "frm", "cls"],
+        names: &[],
+    },
+    LangDef {
+        name: "web",
+        exts: &[
+            "html", "htm", "css", "scss", "sass", "less", "js", "mjs", "cjs", "jsx", "ts", "tsx",
+            "vue", "svelte",
+        ],
+        names: &[],
+    },
+    LangDef {
+        name: "yaml",
+        exts: &["yml", "yaml"],
+        names: &[],
+    },
+];
+
+pub fn find_lang(name: &str) -> Option<&'static LangDef> {
+    LANGS
+        .iter()
+        .find(|lang| lang.name.eq_ignore_ascii_case(name))
+}
+
+/// ファイル名から組み込み言語を 1 つ選ぶ。ファイル名の一致を優先し、
+/// 拡張子は表の順で最初に一致したもの（`h` は c、`css` は web ではなく css）
+pub fn lang_of(file_name: &str) -> Option<&'static LangDef> {
+    if let Some(lang) = LANGS.iter().find(|lang| lang.names.contains(&file_name)) {
+        return Some(lang);
+    }
+    let ext = Path::new(file_name).extension()?.to_str()?;
+    LANGS
+        .iter()
+        .find(|lang| lang.exts.iter().any(|e| e.eq_ignore_ascii_case(ext)))
+}
+
+/// `--list-langs` の出力
+pub fn write_lang_list<W: Write>(out: &mut W) -> std::io::Result<()> {
+    let width = LANGS.iter().map(|lang| lang.name.len()).max().unwrap_or(0);
+    for lang in LANGS {
+        let mut globs: Vec<String> = lang.exts.iter().map(|ext| format!("*.{ext}")).collect();
+        globs.extend(lang.names.iter().map(|name| name.to_string()));
+        writeln!(out, "{:<width$}  {}", lang.name, globs.join(", "))?;
+    }
+    Ok(())
+}
+
+/// `--ext` / `--lang` から得た拡張子とファイル名。
+/// include パターンと同じ集合にコンパイルされ、部分一致の書き換えは受けない。
+#[derive(Debug, Default)]
+pub struct TypePatterns {
+    exts: Vec<String>,
+    names: Vec<String>,
+}
+
+impl TypePatterns {
+    pub fn from_args(exts: &[String], langs: &[String]) -> Result<Self> {
+        let mut types = Self::default();
+        for ext in exts {
+            let ext = ext.trim().trim_start_matches('.');
+            if ext.is_empty() {
+                return Err(anyhow!("invalid --ext value: empty extension"));
             }
-            Ok(Some(PatternList::Glob(builder.build()?)))
+            push_unique(&mut types.exts, ext);
         }
-        PatternSyntax::Regex => {
-            let regex =
-                Regex::new_many(patterns).map_err(|e| anyhow!("invalid regex pattern: {e}"))?;
-            Ok(Some(PatternList::Regex(regex)))
+        for name in langs {
+            let lang = find_lang(name.trim())
+                .ok_or_else(|| anyhow!("unknown --lang value: {name} (see --list-langs)"))?;
+            for ext in lang.exts {
+                push_unique(&mut types.exts, ext);
+            }
+            for file in lang.names {
+                push_unique(&mut types.names, file);
+            }
         }
+        Ok(types)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.exts.is_empty() && self.names.is_empty()
+    }
+
+    /// name/path どちらの match-mode でも一致するグロブと、その元になった拡張子/ファイル名
+    fn globs(&self) -> Vec<(String, &str)> {
+        let mut globs: Vec<(String, &str)> = self
+            .exts
+            .iter()
+            .map(|ext| (format!("*.{}", escape_glob(ext)), ext.as_str()))
+            .collect();
+        for name in &self.names {
+            let escaped = escape_glob(name);
+            globs.push((escaped.clone(), name.as_str()));
+            globs.push((format!("*/{escaped}"), name.as_str()));
+        }
+        globs
+    }
+
+    fn regexes(&self) -> Vec<(String, &str)> {
+        let mut regexes: Vec<(String, &str)> = self
+            .exts
+            .iter()
+            .map(|ext| (format!(r"\.{}$", regex_syntax_escape(ext)), ext.as_str()))
+            .collect();
+        regexes.extend(self.names.iter().map(|name| {
+            (
+                format!(r"(?:^|[/\\]){}$", regex_syntax_escape(name)),
+                name.as_str(),
+            )
+        }));
+        regexes
     }
 }
 
-pub fn build_include_prefixes(
-    root: &Path,
-    patterns: &[String],
-    syntax: PatternSyntax,
-    mode: MatchMode,
-) -> HashSet<PathBuf> {
-    if patterns.is_empty() || !matches!(mode, MatchMode::Path) {
-        return HashSet::new();
+fn push_unique(list: &mut Vec<String>, value: &str) {
+    if !list.iter().any(|v| v == value) {
+        list.push(value.to_string());
+    }
+}
+
+fn escape_glob(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '{' | '}') {
+            escaped.push('[');
+            escaped.push(c);
+            escaped.push(']');
+        } else {
+            escaped.push(c);
+        }
     }
+    escaped
+}
 
-    let mut prefixes = HashSet::new();
+fn regex_syntax_escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if "\\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
-    if matches!(syntax, PatternSyntax::Glob) {
-        for pattern in patterns {
-            let mut buf = PathBuf::new();
-            let normalized = pattern.replace("\\", "/");
-            let parts: Vec<_> = normalized.split('/').filter(|s| !s.is_empty()).collect();
-            let keep_last = normalized.ends_with('/');
+/// 大文字小文字の扱い。パターン先頭の `i:` は個別に大文字小文字を無視させる。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CaseMode {
+    #[default]
+    Sensitive,
+    Insensitive,
+    /// 大文字を含まないパターンだけ大文字小文字を無視する
+    Smart,
+}
 
-            for (idx, segment) in parts.iter().enumerate() {
-                if contains_glob_meta(segment) {
-                    break;
-                }
+impl CaseMode {
+    pub fn from_flags(ignore_case: bool, smart_case: bool) -> Self {
+        if ignore_case {
+            CaseMode::Insensitive
+        } else if smart_case {
+            CaseMode::Smart
+        } else {
+            CaseMode::Sensitive
+        }
+    }
 
-                buf.push(segment);
-                let is_last = idx + 1 == parts.len();
-                if !is_last || keep_last {
-                    prefixes.insert(buf.clone());
-                }
-            }
+    /// `i:` を取り除いたパターン本体と、大文字小文字を無視するかどうかを返す
+    pub fn resolve(self, pattern: &str) -> (&str, bool) {
+        match pattern.strip_prefix("i:") {
+            Some(rest) => (rest, true),
+            None => (pattern, self.ignores_case(pattern)),
         }
     }
 
-    // If a caller provided an absolute path, trim the root prefix so we compare
-    // relative paths consistently during traversal.
-    prefixes
-        .into_iter()
-        .map(|p| p.strip_prefix(root).map(PathBuf::from).unwrap_or(p))
-        .collect()
+    fn ignores_case(self, pattern: &str) -> bool {
+        match self {
+            CaseMode::Sensitive => false,
+            CaseMode::Insensitive => true,
+            CaseMode::Smart => !pattern.chars().any(char::is_uppercase),
+        }
+    }
 }
 
-pub fn include_dir_allowed(
-    root: &Path,
-    dir_path: &Path,
-    include_glob: &Option<PatternList>,
-    include_prefixes: &HashSet<PathBuf>,
-    mode: MatchMode,
-) -> bool {
-    if include_glob.is_none() {
-        return false;
+/// 正規表現に大文字小文字の指定を埋め込む
+fn regex_with_case(pattern: &str, case: CaseMode) -> String {
+    let (body, insensitive) = case.resolve(pattern);
+    if insensitive {
+        format!("(?i:{body})")
+    } else {
+        body.to_string()
     }
+}
 
-    let relative = dir_path
-        .strip_prefix(root)
-        .map(PathBuf::from)
-        .unwrap_or_else(|_| dir_path.to_path_buf());
+fn compile_glob(pattern: &str, insensitive: bool, literal_separator: bool) -> Result<Glob> {
+    GlobBuilder::new(pattern)
+        .case_insensitive(insensitive)
+        .literal_separator(literal_separator)
+        .build()
+        .with_context(|| format!("invalid glob: {pattern}"))
+}
 
-    match mode {
-        MatchMode::Name => true,
-        MatchMode::Path => {
-            include_prefixes.is_empty()
-                || include_prefixes
-                    .iter()
-                    .any(|prefix| relative.starts_with(prefix))
+/// include/exclude パターン 1 つ分の属性
+#[derive(Clone, Copy, Debug)]
+struct Rule {
+    /// コマンドラインでの並び順。後のパターンほど優先される
+    order: usize,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// include または exclude のパターン集合。
+///
+/// gitignore と同じく「最後に一致したパターン」の極性（`!` の有無）で判定する。
+/// `/` を含むグロブはルートからの相対パスに対してアンカーされ、`*` は `/` を跨がない。
+/// `/` を含まないパターンは `--match-mode` に従い名前またはパスに一致させる。
+struct RuleSet {
+    anchored: Option<PatternList>,
+    anchored_rules: Vec<Rule>,
+    floating: Option<PatternList>,
+    floating_rules: Vec<Rule>,
+    has_positive: bool,
+    /// 肯定パターンがすべてアンカー付きのときのリテラル接頭辞（ディレクトリの枝刈り用）
+    literal_prefixes: Option<Vec<PathBuf>>,
+}
+
+impl RuleSet {
+    fn compile(
+        patterns: &[String],
+        syntax: PatternSyntax,
+        allow_partial: bool,
+        types: &TypePatterns,
+        case: CaseMode,
+    ) -> Result<Option<Self>> {
+        if patterns.is_empty() && types.is_empty() {
+            return Ok(None);
+        }
+
+        let mut anchored = Vec::new();
+        let mut anchored_rules = Vec::new();
+        let mut floating = Vec::new();
+        let mut floating_rules = Vec::new();
+        let mut prefixes = Vec::new();
+        let mut has_floating_positive = false;
+        let mut order = 0;
+
+        // 型パターンは先に並べ、ユーザーの `!` 指定で打ち消せるようにする
+        let type_patterns = match syntax {
+            PatternSyntax::Glob => types.globs(),
+            PatternSyntax::Regex => types.regexes(),
+        };
+        for (pattern, source) in type_patterns {
+            let insensitive = case.ignores_case(source);
+            floating.push(CompiledPattern::new(syntax, &pattern, insensitive, false)?);
+            floating_rules.push(Rule {
+                order,
+                negated: false,
+                dir_only: false,
+            });
+            has_floating_positive = true;
+            order += 1;
+        }
+
+        for raw in patterns {
+            let (body, negated) = match raw.strip_prefix('!') {
+                Some(rest) => (rest, true),
+                None => (raw.strip_prefix('\\').unwrap_or(raw), false),
+            };
+            let (body, insensitive) = case.resolve(body);
+            if body.is_empty() {
+                return Err(anyhow!("invalid pattern: {raw}"));
+            }
+
+            if matches!(syntax, PatternSyntax::Regex) {
+                floating.push(CompiledPattern::new(syntax, body, insensitive, false)?);
+                floating_rules.push(Rule {
+                    order,
+                    negated,
+                    dir_only: false,
+                });
+                has_floating_positive |= !negated;
+                order += 1;
+                continue;
+            }
+
+            let (body, dir_only) = match body.strip_suffix('/') {
+                Some(rest) if !rest.is_empty() => (rest, true),
+                _ => (body, false),
+            };
+            let rule = Rule {
+                order,
+                negated,
+                dir_only,
+            };
+            order += 1;
+
+            if body.contains('/') {
+                let body = body.trim_start_matches('/');
+                anchored.push(CompiledPattern::new(syntax, body, insensitive, true)?);
+                anchored_rules.push(rule);
+                if !negated {
+                    prefixes.push(literal_prefix(body, insensitive));
+                }
+            } else {
+                let pattern = if allow_partial && !contains_glob_meta(body) {
+                    format!("*{body}*")
+                } else {
+                    body.to_string()
+                };
+                floating.push(CompiledPattern::new(syntax, &pattern, insensitive, false)?);
+                floating_rules.push(rule);
+                has_floating_positive |= !negated;
+            }
+        }
+
+        let has_positive = has_floating_positive || !prefixes.is_empty();
+        let literal_prefixes = if has_floating_positive {
+            None
+        } else {
+            prefixes.into_iter().collect::<Option<Vec<_>>>()
+        };
+
+        Ok(Some(Self {
+            anchored: PatternList::build(syntax, anchored)?,
+            anchored_rules,
+            floating: PatternList::build(syntax, floating)?,
+            floating_rules,
+            has_positive,
+            literal_prefixes,
+        }))
+    }
+
+    /// 一致したパターンのうち最後のものが肯定なら `Some(true)`、否定なら `Some(false)`
+    fn evaluate(&self, rel: &Path, floating_target: &Path, is_dir: bool) -> Option<bool> {
+        let mut last: Option<Rule> = None;
+        let mut consider = |rules: &[Rule], indices: Vec<usize>| {
+            for idx in indices {
+                let rule = rules[idx];
+                if rule.dir_only && !is_dir {
+                    continue;
+                }
+                if last.is_none_or(|prev| rule.order > prev.order) {
+                    last = Some(rule);
+                }
+            }
+        };
+        if let Some(list) = &self.anchored {
+            consider(&self.anchored_rules, list.matching_indices(rel));
+        }
+        if let Some(list) = &self.floating {
+            consider(&self.floating_rules, list.matching_indices(floating_target));
+        }
+        last.map(|rule| !rule.negated)
+    }
+
+    /// include に一致しないディレクトリでも、その下に一致し得るなら辿る
+    fn may_contain_matches(&self, rel_dir: &Path) -> bool {
+        match &self.literal_prefixes {
+            None => true,
+            Some(prefixes) => prefixes
+                .iter()
+                .any(|prefix| rel_dir.starts_with(prefix) || prefix.starts_with(rel_dir)),
         }
     }
 }
 
-pub fn allow_type(ty: &fs::FileType, types: &[TypeFilter]) -> bool {
-    if types.is_empty() {
-        return true;
+/// グロブのメタ文字より前のディレクトリ部分。大文字小文字を無視する場合は求めない
+fn literal_prefix(pattern: &str, insensitive: bool) -> Option<PathBuf> {
+    if insensitive {
+        return None;
     }
-    let is_dir = ty.is_dir();
-    let is_symlink = ty.is_symlink();
-    let is_file = !is_dir && !is_symlink;
-    types.iter().any(|t| match t {
-        TypeFilter::File => is_file,
-        TypeFilter::Dir => is_dir,
-        TypeFilter::Symlink => is_symlink,
-    })
+    let mut buf = PathBuf::new();
+    let parts: Vec<&str> = pattern.split('/').filter(|s| !s.is_empty()).collect();
+    for (idx, segment) in parts.iter().enumerate() {
+        if contains_glob_meta(segment) || idx + 1 == parts.len() {
+            break;
+        }
+        buf.push(segment);
+    }
+    Some(buf)
 }
 
-fn target_for_glob(root: &Path, path: &Path, mode: MatchMode) -> PathBuf {
-    match mode {
-        MatchMode::Name => path.file_name().map(PathBuf::from).unwrap_or_default(),
-        MatchMode::Path => path.strip_prefix(root).unwrap_or(path).to_path_buf(),
+enum CompiledPattern {
+    Glob(Glob),
+    Regex(String),
+}
+
+impl CompiledPattern {
+    fn new(
+        syntax: PatternSyntax,
+        pattern: &str,
+        insensitive: bool,
+        literal_separator: bool,
+    ) -> Result<Self> {
+        Ok(match syntax {
+            PatternSyntax::Glob => {
+                CompiledPattern::Glob(compile_glob(pattern, insensitive, literal_separator)?)
+            }
+            PatternSyntax::Regex if insensitive => {
+                CompiledPattern::Regex(format!("(?i:{pattern})"))
+            }
+            PatternSyntax::Regex => CompiledPattern::Regex(pattern.to_string()),
+        })
     }
 }
 
 impl PatternList {
-    pub fn is_match(&self, target: &Path) -> bool {
+    fn build(syntax: PatternSyntax, patterns: Vec<CompiledPattern>) -> Result<Option<Self>> {
+        if patterns.is_empty() {
+            return Ok(None);
+        }
+        match syntax {
+            PatternSyntax::Glob => {
+                let mut builder = GlobSetBuilder::new();
+                for pattern in patterns {
+                    if let CompiledPattern::Glob(glob) = pattern {
+                        builder.add(glob);
+                    }
+                }
+                Ok(Some(PatternList::Glob(builder.build()?)))
+            }
+            PatternSyntax::Regex => {
+                let sources: Vec<String> = patterns
+                    .into_iter()
+                    .filter_map(|pattern| match pattern {
+                        CompiledPattern::Regex(source) => Some(source),
+                        CompiledPattern::Glob(_) => None,
+                    })
+                    .collect();
+                let regex =
+                    Regex::new_many(&sources).map_err(|e| anyhow!("invalid regex pattern: {e}"))?;
+                Ok(Some(PatternList::Regex(regex)))
+            }
+        }
+    }
+
+    fn matching_indices(&self, target: &Path) -> Vec<usize> {
         match self {
-            PatternList::Glob(gs) => gs.is_match(target),
-            PatternList::Regex(re) => re.is_match(target.to_string_lossy().as_ref()),
+            PatternList::Glob(gs) => gs.matches(target),
+            PatternList::Regex(re) => {
+                let haystack = target.to_string_lossy();
+                let mut set = PatternSet::new(re.pattern_len());
+                re.which_overlapping_matches(&Input::new(haystack.as_ref()), &mut set);
+                set.iter().map(|id| id.as_usize()).collect()
+            }
         }
     }
 }
 
-pub fn match_globs(
-    root: &Path,
-    path: &Path,
-    include_glob: &Option<PatternList>,
-    exclude_glob: &Option<PatternList>,
+/// include/exclude/`--filter-regex`/`--ext`/`--lang` をまとめて評価するマッチャー。
+/// 通常の走査と `--gitignore on` の走査の両方がこの 1 か所で判定する。
+pub struct Matcher {
+    root: PathBuf,
     mode: MatchMode,
-) -> bool {
-    if let Some(gs) = include_glob {
-        let target = target_for_glob(root, path, mode);
-        if !gs.is_match(&target) {
-            return false;
-        }
+    include: Option<RuleSet>,
+    exclude: Option<RuleSet>,
+    regex: Option<Regex>,
+}
+
+impl Matcher {
+    pub fn from_cli(cli: &Cli, root: &Path) -> Result<Self> {
+        let types = TypePatterns::from_args(&cli.exts, &cli.langs)?;
+        let case = CaseMode::from_flags(cli.ignore_case, cli.smart_case);
+        let include = RuleSet::compile(&cli.includes, cli.pattern_syntax, true, &types, case)?;
+        let exclude = RuleSet::compile(
+            &cli.excludes,
+            cli.pattern_syntax,
+            false,
+            &TypePatterns::default(),
+            case,
+        )?;
+        let regex = match cli.filter_regex.as_deref() {
+            Some(pattern) => Some(
+                Regex::new(&regex_with_case(pattern, case))
+                    .map_err(|err| anyhow!("invalid --filter-regex value: {err}"))?,
+            ),
+            None => None,
+        };
+
+        Ok(Self {
+            root: root.to_path_buf(),
+            mode: cli.match_mode,
+            include,
+            exclude,
+            regex,
+        })
     }
-    if let Some(gs) = exclude_glob {
-        let target = target_for_glob(root, path, mode);
-        if gs.is_match(&target) {
-            return false;
+
+    /// エントリを表示（ディレクトリなら探索）してよいか
+    pub fn allows(&self, path: &Path, is_dir: bool) -> bool {
+        let rel = path.strip_prefix(&self.root).unwrap_or(path);
+        let floating_target = match self.mode {
+            MatchMode::Name => path.file_name().map(Path::new).unwrap_or(rel),
+            MatchMode::Path => rel,
+        };
+
+        if let Some(exclude) = &self.exclude {
+            if exclude.evaluate(rel, floating_target, is_dir) == Some(true) {
+                return false;
+            }
+        }
+
+        if let Some(include) = &self.include {
+            let included = include
+                .evaluate(rel, floating_target, is_dir)
+                .unwrap_or(!include.has_positive);
+            let descend = is_dir && include.may_contain_matches(rel);
+            if !(included || descend) {
+                return false;
+            }
+        }
+
+        if let Some(re) = &self.regex {
+            if !re.is_match(floating_target.to_string_lossy().as_ref()) {
+                return false;
+            }
         }
+
+        true
     }
-    true
+}
+
+pub fn allow_type(ty: &fs::FileType, types: &[TypeFilter]) -> bool {
+    allow_kind(ty.is_dir(), ty.is_symlink(), types)
+}
+
+/// `FileType` を持たないエントリ（`--git-ghosts` の削除済みエントリなど）用
+pub fn allow_kind(is_dir: bool, is_symlink: bool, types: &[TypeFilter]) -> bool {
+    if types.is_empty() {
+        return true;
+    }
+    let is_file = !is_dir && !is_symlink;
+    // LFS のポインタかどうかは中身を読むまで分からないので、ここではファイルとして通す。
+    // ポインタは配下のどこにでもあるので、探すためにディレクトリも通す
+    types.iter().any(|t| match t {
+        TypeFilter::File => is_file,
+        TypeFilter::Lfs => is_file || is_dir,
+        TypeFilter::Dir => is_dir,
+        TypeFilter::Symlink => is_symlink,
+    })
 }
 
 fn contains_glob_meta(pattern: &str) -> bool {
@@ -184,104 +689,174 @@ pub fn color_choice(mode: ColorMode) -> ColorChoice {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use clap::Parser;
+
+    fn matcher(args: &[&str]) -> Matcher {
+        let cli = Cli::parse_from(std::iter::once("printree").chain(args.iter().copied()));
+        Matcher::from_cli(&cli, Path::new("/project")).expect("build matcher")
+    }
+
+    fn allows_file(m: &Matcher, rel: &str) -> bool {
+        m.allows(&Path::new("/project").join(rel), false)
+    }
+
+    fn allows_dir(m: &Matcher, rel: &str) -> bool {
+        m.allows(&Path::new("/project").join(rel), true)
+    }
 
     #[test]
     fn match_mode_path_uses_root_prefix_for_globs() {
-        let include = build_patterns(&["src/utils/**".to_string()], PatternSyntax::Glob, false)
-            .expect("building include glob");
-        let root = Path::new("/project");
-        let included_path = root.join("src/utils/filter.rs");
-        let excluded_path = root.join("src/bin/main.rs");
-
-        assert!(match_globs(
-            root,
-            &included_path,
-            &include,
-            &None::<PatternList>,
-            MatchMode::Path
-        ));
-        assert!(!match_globs(
-            root,
-            &excluded_path,
-            &include,
-            &None::<PatternList>,
-            MatchMode::Path
-        ));
+        let m = matcher(&["--match-mode", "path", "--include", "src/utils/**"]);
+        assert!(allows_file(&m, "src/utils/filter.rs"));
+        assert!(!allows_file(&m, "src/bin/main.rs"));
     }
 
     #[test]
     fn include_patterns_support_partial_match_without_glob() {
-        let include = build_patterns(&["util".to_string()], PatternSyntax::Glob, true)
-            .expect("build patterns")
-            .expect("pattern list");
-        let root = Path::new("/project");
-        let included_path = root.join("src/utils/filter.rs");
-        assert!(include.is_match(&included_path));
+        let m = matcher(&["--match-mode", "path", "--include", "util"]);
+        assert!(allows_file(&m, "src/utils/filter.rs"));
+        assert!(!allows_file(&m, "src/bin/main.rs"));
     }
 
     #[test]
     fn regex_patterns_are_supported() {
-        let include = build_patterns(&[r"src/.+".to_string()], PatternSyntax::Regex, true)
-            .expect("build regex patterns");
-        let root = Path::new("/project");
-        let included_path = root.join("src/utils/filter.rs");
-        assert!(match_globs(
-            root,
-            &included_path,
-            &include,
-            &None::<PatternList>,
-            MatchMode::Path
-        ));
+        let m = matcher(&[
+            "--match-mode",
+            "path",
+            "--pattern-syntax",
+            "regex",
+            "--include",
+            r"src/.+",
+        ]);
+        assert!(allows_file(&m, "src/utils/filter.rs"));
+        assert!(!allows_file(&m, "docs/index.md"));
+    }
+
+    #[test]
+    fn include_dir_allowed_accepts_ancestors_and_rejects_unrelated_dirs() {
+        let m = matcher(&[
+            "--match-mode",
+            "path",
+            "--include",
+            "src/utils/deep/file.rs",
+        ]);
+        assert!(allows_dir(&m, "src"));
+        assert!(allows_dir(&m, "src/utils"));
+        assert!(allows_dir(&m, "src/utils/deep"));
+        assert!(allows_file(&m, "src/utils/deep/file.rs"));
+        assert!(!allows_dir(&m, "docs"));
+        assert!(!allows_file(&m, "src/utils/other.rs"));
     }
 
     #[test]
-    fn include_prefixes_capture_intermediate_directories() {
-        let root = Path::new("/project");
-        let prefixes = build_include_prefixes(
-            root,
-            &["src/utils/deep/file.rs".to_string()],
-            PatternSyntax::Glob,
-            MatchMode::Path,
-        );
+    fn slash_patterns_are_anchored_to_the_root() {
+        let m = matcher(&["--exclude", "/target", "--exclude", "build"]);
+        assert!(!allows_dir(&m, "target"));
+        assert!(allows_dir(&m, "crates/a/target"));
+        assert!(!allows_dir(&m, "build"));
+        assert!(!allows_dir(&m, "crates/a/build"));
 
-        assert!(prefixes.contains(Path::new("src")));
-        assert!(prefixes.contains(Path::new("src/utils")));
-        assert!(prefixes.contains(Path::new("src/utils/deep")));
-        assert!(!prefixes.contains(Path::new("src/utils/deep/file.rs")));
+        // `*` は `/` を跨がず、`**` は任意の階層に一致する
+        let m = matcher(&["--include", "src/*.rs"]);
+        assert!(allows_file(&m, "src/main.rs"));
+        assert!(!allows_file(&m, "src/core/tree.rs"));
+        let m = matcher(&["--include", "src/**/*.rs"]);
+        assert!(allows_file(&m, "src/main.rs"));
+        assert!(allows_file(&m, "src/core/tree.rs"));
+        assert!(!allows_file(&m, "tests/cli.rs"));
     }
 
     #[test]
-    fn include_dir_allowed_accepts_ancestors_and_rejects_unrelated_dirs() {
-        let root = Path::new("/project");
-        let include_glob = build_patterns(
-            &["src/utils/deep/file.rs".to_string()],
-            PatternSyntax::Glob,
-            true,
-        )
-        .expect("build patterns");
-        let prefixes = build_include_prefixes(
-            root,
-            &["src/utils/deep/file.rs".to_string()],
-            PatternSyntax::Glob,
-            MatchMode::Path,
-        );
-
-        let ancestor = root.join("src/utils");
-        assert!(include_dir_allowed(
-            root,
-            &ancestor,
-            &include_glob,
-            &prefixes,
-            MatchMode::Path
-        ));
-
-        let unrelated = root.join("docs");
-        assert!(!include_dir_allowed(
-            root,
-            &unrelated,
-            &include_glob,
-            &prefixes,
-            MatchMode::Path
-        ));
+    fn negated_patterns_and_last_match_wins() {
+        let m = matcher(&["--exclude", "*.log", "--exclude", "!keep.log"]);
+        assert!(!allows_file(&m, "debug.log"));
+        assert!(allows_file(&m, "keep.log"));
+
+        let m = matcher(&["--include", "*.rs", "--include", "!*_test.rs"]);
+        assert!(allows_file(&m, "lib.rs"));
+        assert!(!allows_file(&m, "lib_test.rs"));
+        assert!(!allows_file(&m, "README.md"));
+
+        // 否定だけの include は「それ以外すべて」を意味する
+        let m = matcher(&["--include", "!*.md"]);
+        assert!(allows_file(&m, "lib.rs"));
+        assert!(!allows_file(&m, "README.md"));
+
+        let m = matcher(&["--exclude", r"\!important"]);
+        assert!(!allows_file(&m, "!important"));
+        assert!(allows_file(&m, "important"));
+    }
+
+    #[test]
+    fn trailing_slash_patterns_only_match_directories() {
+        let m = matcher(&["--exclude", "cache/"]);
+        assert!(!allows_dir(&m, "cache"));
+        assert!(allows_file(&m, "cache"));
+    }
+
+    #[test]
+    fn ext_and_lang_patterns_join_the_include_set_without_partial_rewrite() {
+        let m = matcher(&["--ext", ".toml", "--lang", "rust"]);
+        assert!(allows_file(&m, "main.rs"));
+        assert!(allows_file(&m, "src/core/tree.rs"));
+        assert!(allows_file(&m, "Cargo.lock"));
+        assert!(allows_file(&m, "crates/a/Cargo.lock"));
+        assert!(allows_file(&m, "rustfmt.toml"));
+        assert!(!allows_file(&m, "rs.txt"));
+        assert!(!allows_file(&m, "MyCargo.lock"));
+
+        let m = matcher(&[
+            "--match-mode",
+            "path",
+            "--pattern-syntax",
+            "regex",
+            "--lang",
+            "rust",
+        ]);
+        assert!(allows_file(&m, "src/lib.rs"));
+        assert!(allows_file(&m, "crates/a/Cargo.lock"));
+        assert!(!allows_file(&m, "src/lib.rsx"));
+    }
+
+    #[test]
+    fn unknown_lang_is_rejected_and_types_disable_prefix_pruning() {
+        assert!(TypePatterns::from_args(&[], &["klingon".to_string()]).is_err());
+        assert!(TypePatterns::from_args(&["".to_string()], &[]).is_err());
+
+        let m = matcher(&["--include", "src/**", "--ext", "md"]);
+        assert!(allows_dir(&m, "docs"));
+        assert!(allows_file(&m, "docs/index.md"));
+        assert!(!allows_file(&m, "docs/index.html"));
+    }
+
+    #[test]
+    fn case_modes_apply_to_globs_regexes_and_types() {
+        let m = matcher(&["--ignore-case", "--include", "*.jpg"]);
+        assert!(allows_file(&m, "IMG_0001.JPG"));
+
+        let m = matcher(&["--smart-case", "--include", "*.jpg", "--include", "*.PNG"]);
+        assert!(allows_file(&m, "a.JPG"));
+        assert!(allows_file(&m, "a.PNG"));
+        assert!(!allows_file(&m, "a.png"));
+
+        let m = matcher(&[
+            "--match-mode",
+            "path",
+            "--pattern-syntax",
+            "regex",
+            "--include",
+            "i:photo",
+        ]);
+        assert!(allows_file(&m, "PHOTOS/a.jpg"));
+
+        let m = matcher(&["--smart-case", "--ext", "jpg"]);
+        assert!(allows_file(&m, "a.JPG"));
+    }
+
+    #[test]
+    fn insensitive_includes_do_not_restrict_directory_prefixes() {
+        let m = matcher(&["--include", "i:src/utils/*.rs"]);
+        assert!(allows_dir(&m, "SRC"));
+        assert!(allows_file(&m, "SRC/Utils/a.rs"));
     }
 }